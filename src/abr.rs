@@ -0,0 +1,135 @@
+use std::time::{Duration, Instant};
+
+use flowly::{Service, VideoFrame};
+use futures::Stream;
+
+use crate::{EncoderControl, EncoderSettings, Error, FeedbackBus, clock};
+
+/// Delivery conditions observed downstream of the encoder, reported with
+/// [`FeedbackBus::report_delivery`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Feedback {
+    /// Rate the transport actually manages to send, in bits per second.
+    pub send_rate: Option<u32>,
+    /// Fraction of packets lost, `0.0..=1.0`.
+    pub loss: f32,
+    /// Frames or packets waiting in the send queue.
+    pub queue_depth: usize,
+}
+
+/// Decides new encoder settings from delivery feedback.
+pub trait AbrPolicy: Send {
+    /// Returns the settings to switch to, or `None` to keep the current ones.
+    fn decide(&mut self, feedback: &Feedback, current: &EncoderSettings)
+    -> Option<EncoderSettings>;
+}
+
+/// Default policy: backs off multiplicatively on loss or a growing send queue,
+/// probes upwards slowly while the link is clean, and never exceeds what the
+/// transport reports it can send.
+#[derive(Debug, Clone)]
+pub struct LossBasedPolicy {
+    pub min_bitrate: u32,
+    pub max_bitrate: u32,
+    pub max_queue_depth: usize,
+    /// Minimum time between two changes, not enforced on wasm32, which has
+    /// no clock.
+    pub interval: Duration,
+    last_change: Option<Instant>,
+}
+
+impl LossBasedPolicy {
+    pub fn new(min_bitrate: u32, max_bitrate: u32) -> Self {
+        Self {
+            min_bitrate,
+            max_bitrate,
+            max_queue_depth: 8,
+            interval: Duration::from_secs(1),
+            last_change: None,
+        }
+    }
+}
+
+impl AbrPolicy for LossBasedPolicy {
+    fn decide(
+        &mut self,
+        feedback: &Feedback,
+        current: &EncoderSettings,
+    ) -> Option<EncoderSettings> {
+        if self
+            .last_change
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return None;
+        }
+
+        let mut bitrate = current.bitrate as f32;
+
+        if feedback.loss > 0.1 {
+            bitrate *= 1.0 - 0.5 * feedback.loss.min(1.0);
+        } else if feedback.queue_depth > self.max_queue_depth {
+            bitrate *= 0.85;
+        } else if feedback.loss < 0.02 {
+            bitrate *= 1.05;
+        }
+
+        if let Some(rate) = feedback.send_rate {
+            bitrate = bitrate.min(rate as f32 * 1.1);
+        }
+
+        let bitrate = (bitrate as u32).clamp(self.min_bitrate, self.max_bitrate);
+
+        // ignore changes too small to be worth reconfiguring the encoder
+        if bitrate.abs_diff(current.bitrate) < current.bitrate / 100 + 1 {
+            return None;
+        }
+
+        self.last_change = clock::now();
+
+        Some(EncoderSettings {
+            bitrate,
+            ..*current
+        })
+    }
+}
+
+/// Pass-through service placed in front of an [`Openh264Encoder`](crate::Openh264Encoder)
+/// that applies its [`AbrPolicy`] on every frame to the latest feedback on the
+/// [`FeedbackBus`] of the pipeline context.
+pub struct AbrController<P = LossBasedPolicy> {
+    policy: P,
+    control: EncoderControl,
+}
+
+impl<P: AbrPolicy> AbrController<P> {
+    pub fn new(policy: P, control: EncoderControl) -> Self {
+        Self { policy, control }
+    }
+
+    fn evaluate(&mut self, bus: &FeedbackBus) {
+        let Some(feedback) = bus.take_delivery() else {
+            return;
+        };
+
+        let current = self.control.settings();
+
+        if let Some(settings) = self.policy.decide(&feedback, &current) {
+            log::debug!("abr: {current:?} -> {settings:?} on {feedback:?}");
+            self.control.update(settings);
+        }
+    }
+}
+
+impl<F: VideoFrame + 'static, P: AbrPolicy> Service<F> for AbrController<P> {
+    type Out = Result<F, Error>;
+
+    fn handle(&mut self, frame: F, cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            if let Some(bus) = FeedbackBus::of(cx) {
+                self.evaluate(bus);
+            }
+
+            yield Ok(frame);
+        }
+    }
+}
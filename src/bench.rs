@@ -0,0 +1,209 @@
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use openh264::{
+    encoder::{BitRate, Encoder, EncoderConfig, FrameRate},
+    formats::YUVSource,
+};
+
+use crate::{
+    Bt601FullRange, ColorConvert, Error, Library, OutputFormat, YuvPlanes, nal,
+    worker::create_decoder,
+    yuv::{self, Yuv420},
+};
+
+/// Clip decoded by [`benchmark`].
+#[derive(Debug, Clone)]
+pub enum BenchmarkClip {
+    /// A moving gradient of `frames` frames, encoded on the spot with openh264.
+    Synthetic {
+        width: u16,
+        height: u16,
+        frames: usize,
+    },
+    /// An Annex B elementary stream, e.g. a sample recorded from a camera.
+    AnnexB(Bytes),
+}
+
+impl Default for BenchmarkClip {
+    fn default() -> Self {
+        Self::Synthetic {
+            width: 1280,
+            height: 720,
+            frames: 300,
+        }
+    }
+}
+
+/// Configuration of a [`benchmark`] run.
+#[derive(Debug, Clone, Default)]
+pub struct BenchmarkOptions {
+    pub(crate) clip: BenchmarkClip,
+    pub(crate) library: Library,
+    pub(crate) output_format: OutputFormat,
+}
+
+impl BenchmarkOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults to a 300 frame 720p [`BenchmarkClip::Synthetic`] clip.
+    pub fn clip(mut self, clip: BenchmarkClip) -> Self {
+        self.clip = clip;
+        self
+    }
+
+    pub fn library(mut self, library: Library) -> Self {
+        self.library = library;
+        self
+    }
+
+    /// Pixel layout frames are converted to, as a decoder would be configured.
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+}
+
+/// Throughput of a single decoder on the current machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkReport {
+    pub frames: usize,
+    pub width: u16,
+    pub height: u16,
+    /// Splitting the clip into NAL units.
+    pub parse: Duration,
+    /// Time spent in openh264.
+    pub decode: Duration,
+    /// Conversion into the output format.
+    pub convert: Duration,
+}
+
+impl BenchmarkReport {
+    /// Frames per second one decoder sustains on one core.
+    pub fn fps(&self) -> f64 {
+        let total = (self.parse + self.decode + self.convert).as_secs_f64();
+
+        self.frames as f64 / total.max(f64::EPSILON)
+    }
+
+    /// Number of streams at `fps` one core keeps up with.
+    pub fn streams_at(&self, fps: f64) -> f64 {
+        self.fps() / fps.max(f64::EPSILON)
+    }
+}
+
+/// Decodes a clip as fast as possible on the calling thread and reports the
+/// achievable frame rate and per-stage timings, e.g. to size N-camera
+/// deployments automatically. Blocks for the duration of the run.
+pub fn benchmark(options: BenchmarkOptions) -> Result<BenchmarkReport, Error> {
+    let clip = match &options.clip {
+        BenchmarkClip::Synthetic {
+            width,
+            height,
+            frames,
+        } => synthesize(&options.library, *width as usize, *height as usize, *frames)?,
+        BenchmarkClip::AnnexB(data) => data.clone(),
+    };
+
+    let mut decoder = create_decoder(&options.library)?;
+    let mut report = BenchmarkReport {
+        frames: 0,
+        width: 0,
+        height: 0,
+        parse: Duration::ZERO,
+        decode: Duration::ZERO,
+        convert: Duration::ZERO,
+    };
+
+    let started = Instant::now();
+    let units: Vec<_> = nal::nal_units(&clip).collect();
+    report.parse = started.elapsed();
+
+    let mut rgb = Vec::new();
+    let mut unit = Vec::new();
+
+    for nal in units {
+        unit.clear();
+        unit.extend_from_slice(&[0, 0, 0, 1]);
+        unit.extend_from_slice(nal);
+
+        let started = Instant::now();
+        let decoded = decoder.decode(&unit);
+        report.decode += started.elapsed();
+
+        // a broken unit in a recorded sample should not end the run
+        let Ok(Some(frame)) = decoded else {
+            continue;
+        };
+
+        let (width, height) = frame.dimensions();
+        let started = Instant::now();
+
+        match options.output_format {
+            OutputFormat::Rgb8 => {
+                rgb.resize(width * height * 3, 0);
+                Bt601FullRange.i420_to_rgb8(&YuvPlanes::from_decoded(&frame), &mut rgb);
+            }
+            OutputFormat::I420 => {
+                std::hint::black_box((frame.y().to_vec(), frame.u().to_vec(), frame.v().to_vec()));
+            }
+        }
+
+        report.convert += started.elapsed();
+        report.frames += 1;
+        (report.width, report.height) = (width as u16, height as u16);
+    }
+
+    let started = Instant::now();
+
+    if let Ok(remaining) = decoder.flush_remaining() {
+        report.frames += remaining.len();
+    }
+
+    report.decode += started.elapsed();
+
+    Ok(report)
+}
+
+/// Encodes a diagonal gradient that moves every frame, so inter frames carry
+/// motion like a real scene would.
+fn synthesize(
+    library: &Library,
+    width: usize,
+    height: usize,
+    frames: usize,
+) -> Result<Bytes, Error> {
+    let (width, height) = (width.max(16) & !1, height.max(16) & !1);
+    let (cw, ch) = yuv::chroma_dimensions(width, height);
+
+    let config = EncoderConfig::new()
+        .bitrate(BitRate::from_bps((width * height * 4) as u32))
+        .max_frame_rate(FrameRate::from_hz(30.0));
+
+    let mut encoder = Encoder::with_api_config(library.load()?, config)?;
+    let mut clip = Vec::new();
+
+    for idx in 0..frames {
+        let y: Vec<u8> = (0..width * height)
+            .map(|pos| ((pos % width + pos / width + idx * 4) % 220 + 16) as u8)
+            .collect();
+        let u: Vec<u8> = (0..cw * ch)
+            .map(|pos| ((pos % cw + idx) % 224 + 16) as u8)
+            .collect();
+        let v = vec![128; cw * ch];
+
+        let picture = Yuv420::from_planes(
+            width,
+            height,
+            (width, cw, cw),
+            (y.into(), u.into(), v.into()),
+        )
+        .expect("planes are sized for the picture");
+
+        clip.extend(encoder.encode(&picture)?.to_vec());
+    }
+
+    Ok(clip.into())
+}
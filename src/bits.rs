@@ -0,0 +1,114 @@
+/// MSB-first reader over an RBSP, with the Exp-Golomb codes H.264 headers use.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+
+        self.pos += 1;
+
+        Some(bit == 1)
+    }
+
+    pub(crate) fn read_bits(&mut self, count: u32) -> Option<u32> {
+        (0..count).try_fold(0, |value, _| Some(value << 1 | self.read_bit()? as u32))
+    }
+
+    pub(crate) fn read_ue(&mut self) -> Option<u32> {
+        let mut zeroes = 0;
+
+        while !self.read_bit()? {
+            zeroes += 1;
+
+            if zeroes > 31 {
+                return None;
+            }
+        }
+
+        Some(((1u64 << zeroes) - 1 + self.read_bits(zeroes)? as u64) as u32)
+    }
+
+    pub(crate) fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()? as i64;
+
+        Some(if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        } as i32)
+    }
+}
+
+/// MSB-first writer producing an RBSP.
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn write_bit(&mut self, bit: bool) {
+        if self.bits % 8 == 0 {
+            self.out.push(0);
+        }
+
+        if bit {
+            *self.out.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+
+        self.bits += 1;
+    }
+
+    pub(crate) fn write_bits(&mut self, value: u32, count: u32) {
+        for idx in (0..count).rev() {
+            self.write_bit((value >> idx) & 1 == 1);
+        }
+    }
+
+    pub(crate) fn write_ue(&mut self, value: u32) {
+        let value = value as u64 + 1;
+        let len = 64 - value.leading_zeros();
+
+        self.write_bits(0, len - 1);
+
+        for idx in (0..len).rev() {
+            self.write_bit((value >> idx) & 1 == 1);
+        }
+    }
+
+    pub(crate) fn write_se(&mut self, value: i32) {
+        let value = value as i64;
+
+        self.write_ue(if value > 0 { 2 * value - 1 } else { -2 * value } as u32);
+    }
+
+    #[inline]
+    pub(crate) fn is_aligned(&self) -> bool {
+        self.bits % 8 == 0
+    }
+
+    /// Appends `rbsp_trailing_bits` and returns the bytes.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.write_bit(true);
+
+        while !self.is_aligned() {
+            self.write_bit(false);
+        }
+
+        self.out
+    }
+
+    /// Returns the bytes, padding a partial last byte with zeroes.
+    #[inline]
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.out
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::{Arc, Mutex};
+
+use flowly::MemBlock;
+
+use crate::DecodedFrame;
+
+/// Memory block type decoded planes are written into.
+///
+/// Implement this for pooled, pinned or device-adjacent flowly memory blocks
+/// to have the decoder write pixels into them directly, without going through
+/// a `Vec<u8>` first.
+pub trait FrameBuffer: MemBlock + Send + 'static {
+    /// Allocates an initialized block of `len` bytes, contents are overwritten.
+    fn alloc(len: usize) -> Self;
+
+    fn as_slice(&self) -> &[u8];
+
+    fn as_mut_slice(&mut self) -> &mut [u8];
+
+    /// Borrows the block as a frame chunk.
+    fn chunk_ref(&self) -> Self::Ref<'_>;
+}
+
+impl FrameBuffer for Vec<u8> {
+    #[inline]
+    fn alloc(len: usize) -> Self {
+        vec![0; len]
+    }
+
+    #[inline]
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+
+    #[inline]
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        self
+    }
+
+    #[inline]
+    fn chunk_ref(&self) -> Self::Ref<'_> {
+        self.as_slice()
+    }
+}
+
+/// Provides the memory decoded planes are written into, e.g. blocks from an
+/// arena, hugepages or pinned memory, set with
+/// [`DecoderParts::allocator`](crate::DecoderParts::allocator).
+///
+/// Implemented for closures. Decoders call [`FrameBuffer::alloc`] unless one is set.
+pub trait FrameAllocator<M>: Send {
+    /// Allocates an initialized block of `len` bytes, contents are overwritten.
+    fn alloc(&self, len: usize) -> M;
+}
+
+impl<M, F: Fn(usize) -> M + Send> FrameAllocator<M> for F {
+    #[inline]
+    fn alloc(&self, len: usize) -> M {
+        self(len)
+    }
+}
+
+/// Allocates with [`FrameBuffer::alloc`].
+pub(crate) struct DefaultAllocator;
+
+impl<M: FrameBuffer> FrameAllocator<M> for DefaultAllocator {
+    #[inline]
+    fn alloc(&self, len: usize) -> M {
+        M::alloc(len)
+    }
+}
+
+/// Allocator that hands out buffers returned from earlier frames, so a
+/// decoder at steady state runs without per-frame allocations.
+///
+/// Set it with [`DecoderParts::allocator`](crate::DecoderParts::allocator)
+/// and hand frames back with [`recycle`](Self::recycle) once done with them.
+/// Frames that are simply dropped free their buffers as usual, the pool then
+/// allocates new ones.
+#[derive(Debug)]
+pub struct FramePool<M = Vec<u8>> {
+    free: Arc<Mutex<Vec<M>>>,
+    capacity: usize,
+}
+
+impl<M: FrameBuffer> FramePool<M> {
+    /// Keeps up to `capacity` buffers, e.g. three per frame of the decode-ahead
+    /// window for [`OutputFormat::I420`](crate::OutputFormat::I420) output.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            free: Default::default(),
+            capacity,
+        }
+    }
+
+    /// Returns the buffers of `frame` to the pool.
+    pub fn recycle<S>(&self, frame: DecodedFrame<S, M>) {
+        let mut free = self.free.lock().unwrap();
+        let buffers = std::iter::once(frame.data).chain(frame.chroma.into_iter().flatten());
+
+        for buffer in buffers {
+            if free.len() >= self.capacity {
+                break;
+            }
+
+            free.push(buffer);
+        }
+    }
+
+    /// Buffers waiting to be reused.
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+impl<M> Clone for FramePool<M> {
+    fn clone(&self) -> Self {
+        Self {
+            free: self.free.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<M: FrameBuffer> FrameAllocator<M> for FramePool<M> {
+    fn alloc(&self, len: usize) -> M {
+        let mut free = self.free.lock().unwrap();
+
+        match free
+            .iter()
+            .position(|buffer| buffer.as_slice().len() == len)
+        {
+            Some(pos) => free.swap_remove(pos),
+            None => {
+                drop(free);
+                M::alloc(len)
+            }
+        }
+    }
+}
+
+/// Copies `data` into a newly allocated block.
+#[inline]
+pub(crate) fn copy_to<M: FrameBuffer>(allocator: &dyn FrameAllocator<M>, data: &[u8]) -> M {
+    let mut block = allocator.alloc(data.len());
+    block.as_mut_slice().copy_from_slice(data);
+    block
+}
@@ -0,0 +1,349 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use flowly::{
+    DataFrame, EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, Service, VideoFrame,
+};
+use futures::Stream;
+
+use crate::Error;
+
+const MAGIC: &[u8; 8] = b"OH264CAP";
+const VERSION: u8 = 1;
+/// Upper bound on chunks per record, far above what an encoder splits a frame into.
+const MAX_CHUNKS: u32 = 4096;
+
+/// Encoded frame recorded by a [`CaptureRecorder`] or read back by a [`CaptureReplayer`].
+#[derive(Debug, Clone)]
+pub struct CapturedFrame<S> {
+    pub timestamp: u64,
+    pub flags: FrameFlags,
+    pub width: u16,
+    pub height: u16,
+    pub chunks: Vec<Bytes>,
+    pub(crate) source: S,
+}
+
+impl<S: FrameSource> DataFrame for CapturedFrame<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        self.chunks.iter().map(|chunk| &chunk[..])
+    }
+
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        self.chunks.into_iter()
+    }
+}
+
+impl<S: FrameSource> Frame for CapturedFrame<S> {
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn codec(&self) -> Fourcc {
+        Fourcc::VIDEO_H264
+    }
+
+    fn flags(&self) -> FrameFlags {
+        self.flags
+    }
+}
+
+impl<S: FrameSource> VideoFrame for CapturedFrame<S> {
+    fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn bit_depth(&self) -> u8 {
+        8
+    }
+}
+
+impl<S: FrameSource> EncodedFrame for CapturedFrame<S> {}
+
+/// Pass-through service placed in front of a decoder that records every
+/// encoded frame (timestamp, flags, dimensions and bytes) to a capture file,
+/// so a failing stream can be attached to a bug report and replayed with a
+/// [`CaptureReplayer`].
+pub struct CaptureRecorder {
+    writer: BufWriter<File>,
+}
+
+impl CaptureRecorder {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+
+        Ok(Self { writer })
+    }
+
+    fn record(&mut self, frame: &CapturedFrame<impl FrameSource>) -> std::io::Result<()> {
+        write_frame(&mut self.writer, frame)?;
+
+        // keep the capture usable if the process dies right after a bad frame
+        self.writer.flush()
+    }
+}
+
+impl<F: EncodedFrame + VideoFrame + 'static> Service<F> for CaptureRecorder {
+    type Out = Result<CapturedFrame<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let (width, height) = frame.dimensions();
+
+            let captured = CapturedFrame {
+                timestamp: frame.timestamp(),
+                flags: frame.flags(),
+                width,
+                height,
+                source: frame.source().clone(),
+                chunks: frame.into_chunks().map(|chunk| chunk.into_cpu_bytes()).collect(),
+            };
+
+            match self.record(&captured) {
+                Ok(()) => yield Ok(captured),
+                Err(err) => yield Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Reads a capture file written by a [`CaptureRecorder`] and yields its frames
+/// in recorded order, ready to be fed to a decoder.
+pub struct CaptureReplayer<S> {
+    _source: PhantomData<fn() -> S>,
+}
+
+impl<S> CaptureReplayer<S> {
+    pub fn new() -> Self {
+        Self {
+            _source: PhantomData,
+        }
+    }
+}
+
+impl<S> Default for CaptureReplayer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: FrameSource + Default> Service<PathBuf> for CaptureReplayer<S> {
+    type Out = Result<CapturedFrame<S>, Error>;
+
+    fn handle(&mut self, path: PathBuf, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let mut reader = match open_capture(&path) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    yield Err(err);
+                    return;
+                }
+            };
+
+            loop {
+                match read_frame(&mut reader) {
+                    Ok(Some(frame)) => yield Ok(frame),
+                    Ok(None) => break,
+                    Err(err) => {
+                        yield Err(err.into());
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn open_capture(path: &Path) -> Result<BufReader<File>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; 9];
+
+    reader.read_exact(&mut header)?;
+
+    if &header[..8] != MAGIC || header[8] != VERSION {
+        return Err(
+            std::io::Error::new(ErrorKind::InvalidData, "not an openh264 capture file").into(),
+        );
+    }
+
+    Ok(reader)
+}
+
+fn write_frame<S>(w: &mut impl Write, frame: &CapturedFrame<S>) -> std::io::Result<()> {
+    w.write_all(&frame.timestamp.to_le_bytes())?;
+    w.write_all(&frame.flags.bits().to_le_bytes())?;
+    w.write_all(&frame.width.to_le_bytes())?;
+    w.write_all(&frame.height.to_le_bytes())?;
+    w.write_all(&(frame.chunks.len() as u32).to_le_bytes())?;
+
+    for chunk in &frame.chunks {
+        w.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        w.write_all(chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the next record, `None` at a clean end of file, i.e. one right
+/// between two records. Lengths are not trusted beyond the data actually there.
+fn read_frame<S: Default>(reader: &mut impl Read) -> std::io::Result<Option<CapturedFrame<S>>> {
+    let mut timestamp = [0; 8];
+
+    match read_full(reader, &mut timestamp)? {
+        0 => return Ok(None),
+        8 => (),
+        _ => return Err(ErrorKind::UnexpectedEof.into()),
+    }
+
+    let flags = read_u32(reader)?;
+    let width = read_u16(reader)?;
+    let height = read_u16(reader)?;
+    let count = read_u32(reader)?;
+
+    if count > MAX_CHUNKS {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("capture record with {count} chunks"),
+        ));
+    }
+
+    let mut chunks = Vec::with_capacity(count.min(64) as usize);
+
+    for _ in 0..count {
+        let len = read_u32(reader)? as u64;
+        let mut chunk = Vec::new();
+
+        reader.by_ref().take(len).read_to_end(&mut chunk)?;
+
+        if chunk.len() as u64 != len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        chunks.push(chunk.into());
+    }
+
+    Ok(Some(CapturedFrame {
+        timestamp: u64::from_le_bytes(timestamp),
+        flags: FrameFlags::from_bits_retain(flags),
+        width,
+        height,
+        chunks,
+        source: S::default(),
+    }))
+}
+
+/// Fills as much of `buf` as the reader has left, returns the bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u16(reader: &mut impl Read) -> std::io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp: u64, flags: FrameFlags, chunks: &[&'static [u8]]) -> CapturedFrame<()> {
+        CapturedFrame {
+            timestamp,
+            flags,
+            width: 320,
+            height: 240,
+            chunks: chunks.iter().copied().map(Bytes::from_static).collect(),
+            source: (),
+        }
+    }
+
+    #[test]
+    fn capture_roundtrip() {
+        let frames = [
+            frame(
+                0,
+                FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME,
+                &[&[0, 0, 0, 1, 0x67, 0x42], &[0, 0, 0, 1, 0x65, 0x88]],
+            ),
+            frame(40, FrameFlags::VIDEO_STREAM, &[&[0, 0, 0, 1, 0x41, 0x9a]]),
+            frame(80, FrameFlags::VIDEO_STREAM, &[]),
+        ];
+
+        let mut file = Vec::new();
+
+        for frame in &frames {
+            write_frame(&mut file, frame).unwrap();
+        }
+
+        let mut reader = &file[..];
+
+        for expected in &frames {
+            let frame = read_frame::<()>(&mut reader).unwrap().unwrap();
+
+            assert_eq!(frame.timestamp, expected.timestamp);
+            assert_eq!(frame.flags, expected.flags);
+            assert_eq!((frame.width, frame.height), (320, 240));
+            assert_eq!(frame.chunks, expected.chunks);
+        }
+
+        assert!(read_frame::<()>(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_records_are_errors() {
+        let mut file = Vec::new();
+        write_frame(&mut file, &frame(0, FrameFlags::VIDEO_STREAM, &[&[1; 32]])).unwrap();
+
+        for len in [3, 8, 20, file.len() - 1] {
+            let err = read_frame::<()>(&mut &file[..len]).unwrap_err();
+
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "cut at {len}");
+        }
+    }
+
+    #[test]
+    fn chunk_count_is_limited() {
+        let mut file = Vec::new();
+        write_frame(&mut file, &frame(0, FrameFlags::VIDEO_STREAM, &[])).unwrap();
+
+        // claim more chunks than any frame has, with no data behind them
+        let count = file.len() - 4;
+        file[count..].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let err = read_frame::<()>(&mut &file[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
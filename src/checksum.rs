@@ -0,0 +1,88 @@
+use std::fmt;
+
+use openh264::{decoder::DecodedYUV, formats::YUVSource};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::yuv;
+
+/// Hash function used for [`DecodedFrame::checksum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// MD5, as printed by ffmpeg's `framemd5` muxer for `yuv420p` output.
+    Md5,
+    /// 64-bit XXH3, much cheaper when no reference tool output has to be matched.
+    Xxh3,
+}
+
+/// Checksum of the visible I420 pixels of a frame as openh264 decoded it,
+/// plane after plane, with row padding left out so it does not depend on
+/// decoder strides or the output format. Placeholders hash the planes of their
+/// color, or carry the checksum of the frame they repeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameChecksum {
+    Md5([u8; 16]),
+    Xxh3(u64),
+}
+
+impl fmt::Display for FrameChecksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Md5(digest) => digest.iter().try_for_each(|b| write!(f, "{b:02x}")),
+            Self::Xxh3(hash) => write!(f, "{hash:016x}"),
+        }
+    }
+}
+
+/// Hashes a picture straight out of openh264, before any RGB conversion.
+pub(crate) fn compute_decoded(
+    frame: &DecodedYUV<'_>,
+    algorithm: ChecksumAlgorithm,
+) -> FrameChecksum {
+    let (width, height) = frame.dimensions();
+    let (cw, ch) = yuv::chroma_dimensions(width, height);
+    let (ys, us, vs) = frame.strides();
+
+    digest(algorithm, |f| {
+        visible_rows(frame.y(), width, height, ys, f);
+        visible_rows(frame.u(), cw, ch, us, f);
+        visible_rows(frame.v(), cw, ch, vs, f);
+    })
+}
+
+/// Hashes an I420 picture of a single `(y, u, v)` color, for solid color
+/// placeholders in either output format.
+pub(crate) fn compute_solid(
+    (width, height): (usize, usize),
+    (y, u, v): (u8, u8, u8),
+    algorithm: ChecksumAlgorithm,
+) -> FrameChecksum {
+    let (cw, ch) = yuv::chroma_dimensions(width, height);
+    let (luma, u, v) = (vec![y; width], vec![u; cw], vec![v; cw]);
+
+    digest(algorithm, |f| {
+        (0..height).for_each(|_| f(&luma));
+        (0..ch).for_each(|_| f(&u));
+        (0..ch).for_each(|_| f(&v));
+    })
+}
+
+fn digest(algorithm: ChecksumAlgorithm, rows: impl FnOnce(&mut dyn FnMut(&[u8]))) -> FrameChecksum {
+    match algorithm {
+        ChecksumAlgorithm::Md5 => {
+            let mut ctx = md5::Context::new();
+            rows(&mut |row| ctx.consume(row));
+            FrameChecksum::Md5(ctx.compute().0)
+        }
+        ChecksumAlgorithm::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            rows(&mut |row| hasher.update(row));
+            FrameChecksum::Xxh3(hasher.digest())
+        }
+    }
+}
+
+fn visible_rows(data: &[u8], row: usize, rows: usize, stride: usize, f: &mut dyn FnMut(&[u8])) {
+    for line in data.chunks(stride.max(1)).take(rows) {
+        f(&line[..row.min(line.len())]);
+    }
+}
@@ -0,0 +1,19 @@
+use std::time::{Duration, Instant};
+
+/// `Instant` is not available on wasm32, where nothing is timed.
+#[cfg(not(target_arch = "wasm32"))]
+#[inline]
+pub(crate) fn now() -> Option<Instant> {
+    Some(Instant::now())
+}
+
+#[cfg(target_arch = "wasm32")]
+#[inline]
+pub(crate) fn now() -> Option<Instant> {
+    None
+}
+
+#[inline]
+pub(crate) fn elapsed(started: Option<Instant>) -> Duration {
+    started.map(|at| at.elapsed()).unwrap_or_default()
+}
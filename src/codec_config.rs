@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::nal::{self, NalType};
+
+/// Parameter sets of an H.264 stream, as needed by MP4/fMP4 and HLS segmenters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecConfig {
+    pub sps: Vec<Bytes>,
+    pub pps: Vec<Bytes>,
+}
+
+impl CodecConfig {
+    /// Collects the SPS and PPS NAL units of an Annex B access unit,
+    /// `None` unless it carries at least one of each.
+    pub fn from_access_unit(data: &[u8]) -> Option<Self> {
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+
+        for unit in nal::nal_units(data) {
+            match NalType::from_header(unit[0]) {
+                NalType::Sps if unit.len() >= 4 => sps.push(Bytes::copy_from_slice(unit)),
+                NalType::Pps => pps.push(Bytes::copy_from_slice(unit)),
+                _ => (),
+            }
+        }
+
+        (!sps.is_empty() && !pps.is_empty()).then_some(Self { sps, pps })
+    }
+
+    /// Puts the parameter sets in front of an access unit, for decoders that
+    /// start mid-stream.
+    pub(crate) fn prepend_to(&self, data: &[u8]) -> Bytes {
+        let mut out = BytesMut::new();
+
+        for unit in self.sps.iter().chain(&self.pps) {
+            out.put_slice(&[0, 0, 0, 1]);
+            out.put_slice(unit);
+        }
+
+        out.put_slice(data);
+        out.freeze()
+    }
+
+    /// Byte `idx` of the first SPS, `None` if there is none or it is truncated.
+    #[inline]
+    fn sps_byte(&self, idx: usize) -> Option<u8> {
+        self.sps.first()?.get(idx).copied()
+    }
+
+    #[inline]
+    pub fn profile_idc(&self) -> Option<u8> {
+        self.sps_byte(1)
+    }
+
+    #[inline]
+    pub fn constraint_flags(&self) -> Option<u8> {
+        self.sps_byte(2)
+    }
+
+    #[inline]
+    pub fn level_idc(&self) -> Option<u8> {
+        self.sps_byte(3)
+    }
+
+    /// RFC 6381 codec string, e.g. `avc1.42c01f`.
+    pub fn codec_string(&self) -> Option<String> {
+        Some(format!(
+            "avc1.{:02x}{:02x}{:02x}",
+            self.profile_idc()?,
+            self.constraint_flags()?,
+            self.level_idc()?
+        ))
+    }
+
+    /// Serializes an `AVCDecoderConfigurationRecord` (the payload of an `avcC`
+    /// box) with 4-byte NAL lengths. The high profile chroma extension is not
+    /// written. `None` without a complete SPS.
+    pub fn to_avcc(&self) -> Option<Bytes> {
+        let size = 7 + self
+            .sps
+            .iter()
+            .chain(&self.pps)
+            .map(|p| p.len() + 2)
+            .sum::<usize>();
+        let mut out = BytesMut::with_capacity(size);
+
+        out.put_u8(1);
+        out.put_u8(self.profile_idc()?);
+        out.put_u8(self.constraint_flags()?);
+        out.put_u8(self.level_idc()?);
+        out.put_u8(0xfc | 3);
+        out.put_u8(0xe0 | self.sps.len().min(31) as u8);
+
+        for sps in self.sps.iter().take(31) {
+            out.put_u16(sps.len() as u16);
+            out.put_slice(sps);
+        }
+
+        out.put_u8(self.pps.len().min(255) as u8);
+
+        for pps in self.pps.iter().take(255) {
+            out.put_u16(pps.len() as u16);
+            out.put_slice(pps);
+        }
+
+        Some(out.freeze())
+    }
+}
+
+/// Parameter sets seen so far in a stream, which may send its SPS and PPS in
+/// separate chunks or frames.
+#[derive(Debug, Default)]
+pub(crate) struct ConfigTracker {
+    sps: BTreeMap<u32, Bytes>,
+    pps: BTreeMap<u32, Bytes>,
+}
+
+impl ConfigTracker {
+    /// Takes in the parameter sets of an Annex B chunk, replacing those with
+    /// the same id. Returns the config in effect if it changed and is complete.
+    pub(crate) fn update(&mut self, data: &[u8]) -> Option<CodecConfig> {
+        let mut changed = false;
+
+        for unit in nal::nal_units(data) {
+            match NalType::from_header(unit[0]) {
+                NalType::Sps if unit.len() >= 4 => {
+                    let Some(id) = nal::parameter_set_id(unit, 3) else {
+                        continue;
+                    };
+
+                    if self.sps.get(&id).is_none_or(|last| last[..] != *unit) {
+                        // PPS are parsed against their SPS, a new SPS needs them resent
+                        self.pps.clear();
+                        self.sps.insert(id, Bytes::copy_from_slice(unit));
+                        changed = true;
+                    }
+                }
+                NalType::Pps => {
+                    let Some(id) = nal::parameter_set_id(unit, 0) else {
+                        continue;
+                    };
+
+                    if self.pps.get(&id).is_none_or(|last| last[..] != *unit) {
+                        self.pps.insert(id, Bytes::copy_from_slice(unit));
+                        changed = true;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        (changed && !self.sps.is_empty() && !self.pps.is_empty()).then(|| CodecConfig {
+            sps: self.sps.values().cloned().collect(),
+            pps: self.pps.values().cloned().collect(),
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use openh264::{decoder::DecodedYUV, formats::YUVSource};
+
+/// A decoded I420 picture as handed to a [`ColorConvert`] implementation.
+///
+/// Rows are `strides` bytes apart and may be longer than the visible width.
+#[derive(Debug, Clone, Copy)]
+pub struct YuvPlanes<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub y: &'a [u8],
+    pub u: &'a [u8],
+    pub v: &'a [u8],
+    /// Strides of the Y, U and V planes.
+    pub strides: (usize, usize, usize),
+}
+
+impl<'a> YuvPlanes<'a> {
+    pub(crate) fn from_decoded(frame: &'a DecodedYUV<'a>) -> Self {
+        let (width, height) = frame.dimensions();
+
+        Self {
+            width,
+            height,
+            y: frame.y(),
+            u: frame.u(),
+            v: frame.v(),
+            strides: frame.strides(),
+        }
+    }
+}
+
+/// Converts decoded pictures into [`OutputFormat::Rgb8`](crate::OutputFormat::Rgb8)
+/// output, e.g. with a GPU or libyuv, set with
+/// [`DecoderOptions::color_converter`](crate::DecoderOptions::color_converter).
+///
+/// Decoders use [`Bt601FullRange`] unless one is set.
+pub trait ColorConvert: Send + Sync + std::fmt::Debug {
+    /// Writes `planes` as packed RGB rows of `width * 3` bytes into `out`,
+    /// which is exactly `width * height * 3` bytes long.
+    fn i420_to_rgb8(&self, planes: &YuvPlanes<'_>, out: &mut [u8]);
+}
+
+/// Default converter, full range BT.601 with the coefficients of openh264's
+/// own `write_rgb8`, so output matches its conversion.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bt601FullRange;
+
+impl ColorConvert for Bt601FullRange {
+    fn i420_to_rgb8(&self, planes: &YuvPlanes<'_>, out: &mut [u8]) {
+        let (ys, us, vs) = planes.strides;
+
+        for (row, rgb) in out.chunks_exact_mut(planes.width * 3).enumerate() {
+            let y = &planes.y[row * ys..][..planes.width];
+            let u = &planes.u[row / 2 * us..];
+            let v = &planes.v[row / 2 * vs..];
+
+            for (col, (pixel, &luma)) in rgb.chunks_exact_mut(3).zip(y).enumerate() {
+                let luma = luma as f32;
+                let u = u[col / 2] as f32 - 128.0;
+                let v = v[col / 2] as f32 - 128.0;
+
+                // float to int casts saturate
+                pixel[0] = (luma + 1.402 * v) as u8;
+                pixel[1] = (luma - 0.344 * u - 0.714 * v) as u8;
+                pixel[2] = (luma + 1.772 * u) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use bytes::Bytes;
+use flowly::{DataFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, VideoFrame};
+use opencv::{
+    boxed_ref::BoxedRef,
+    core::{CV_8UC1, CV_8UC3, Mat, MatTraitConst, MatTraitConstManual, MatTraitManual, Scalar},
+    imgproc,
+};
+
+use crate::{DecodedFrame, Error, FrameBuffer, OutputFormat, yuv};
+
+impl<S, M: FrameBuffer> DecodedFrame<S, M> {
+    /// Borrows [`OutputFormat::Rgb8`] pixels as a `CV_8UC3` Mat without
+    /// copying. Channels are in RGB order, convert with `COLOR_RGB2BGR` for
+    /// functions that expect BGR.
+    pub fn as_mat(&self) -> Result<BoxedRef<'_, Mat>, Error> {
+        if self.format() != OutputFormat::Rgb8 {
+            return Err(Error::UnsupportedPixelFormat(Fourcc::PIXEL_FORMAT_I420));
+        }
+
+        let data = self.data.as_slice();
+
+        // SAFETY: the Mat borrows `data`, which outlives the returned reference
+        // and is sized for `height` rows of `strides.0` bytes
+        let mat = unsafe {
+            Mat::new_rows_cols_with_data_unsafe(
+                self.height as i32,
+                self.width as i32,
+                CV_8UC3,
+                data.as_ptr() as *mut _,
+                self.strides.0,
+            )?
+        };
+
+        Ok(BoxedRef::from(mat))
+    }
+
+    /// Copies the frame into an owned Mat: `CV_8UC3` RGB for
+    /// [`OutputFormat::Rgb8`], or a `height * 3 / 2` row `CV_8UC1` Mat with
+    /// the Y, U and V planes stacked for [`OutputFormat::I420`], as
+    /// `COLOR_YUV2BGR_I420` expects.
+    pub fn to_mat(&self) -> Result<Mat, Error> {
+        let Some([u, v]) = &self.chroma else {
+            return Ok(self.as_mat()?.try_clone()?);
+        };
+
+        let (w, h) = (self.width as usize, self.height as usize);
+        let (cw, ch) = yuv::chroma_dimensions(w, h);
+        let (y_stride, uv_stride) = self.strides;
+
+        let mut mat = Mat::new_rows_cols_with_default(
+            (h + ch * 2) as i32,
+            w as i32,
+            CV_8UC1,
+            Scalar::all(0.),
+        )?;
+        let out = mat.data_bytes_mut()?;
+
+        let (luma, chroma) = out.split_at_mut(w * h);
+        let (out_u, out_v) = chroma.split_at_mut(cw * ch);
+
+        copy_rows(self.data.as_slice(), y_stride, luma, w);
+        copy_rows(u.as_slice(), uv_stride, out_u, cw);
+        copy_rows(v.as_slice(), uv_stride, out_v, cw);
+
+        Ok(mat)
+    }
+}
+
+fn copy_rows(src: &[u8], stride: usize, dst: &mut [u8], row: usize) {
+    for (dst, src) in dst.chunks_exact_mut(row).zip(src.chunks(stride)) {
+        dst.copy_from_slice(&src[..row]);
+    }
+}
+
+/// Raw frame for an [`Openh264Encoder`](crate::Openh264Encoder) built from a
+/// BGR `CV_8UC3` Mat, as read by `VideoCapture`, with the encoder's default
+/// [`PixelFormat::Rgb8`](crate::PixelFormat::Rgb8) input format.
+#[derive(Debug, Clone)]
+pub struct MatFrame<S> {
+    pub timestamp: u64,
+    pub width: u16,
+    pub height: u16,
+    data: Bytes,
+    source: S,
+}
+
+impl<S> MatFrame<S> {
+    /// Converts `mat` to RGB, the only copy on the way into the encoder.
+    pub fn new(mat: &Mat, timestamp: u64, source: S) -> Result<Self, Error> {
+        if mat.typ() != CV_8UC3 {
+            return Err(Error::UnsupportedPixelFormat(Fourcc::PIXEL_FORMAT_RGB888));
+        }
+
+        let mut rgb = Mat::default();
+        imgproc::cvt_color_def(mat, &mut rgb, imgproc::COLOR_BGR2RGB)?;
+
+        // fails unless the pixels are continuous, which MatBuffer relies on
+        rgb.data_bytes()?;
+
+        Ok(Self {
+            timestamp,
+            width: mat.cols() as u16,
+            height: mat.rows() as u16,
+            data: Bytes::from_owner(MatBuffer(rgb)),
+            source,
+        })
+    }
+}
+
+/// Continuous Mat backing the bytes of a [`MatFrame`], so its pixels are not copied again.
+struct MatBuffer(Mat);
+
+impl AsRef<[u8]> for MatBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.0.data_bytes().unwrap_or_default()
+    }
+}
+
+impl<S: FrameSource> DataFrame for MatFrame<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        std::iter::once(&self.data[..])
+    }
+
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        std::iter::once(self.data)
+    }
+}
+
+impl<S: FrameSource> Frame for MatFrame<S> {
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn codec(&self) -> Fourcc {
+        Fourcc::PIXEL_FORMAT_RGB888
+    }
+
+    fn flags(&self) -> FrameFlags {
+        FrameFlags::VIDEO_STREAM
+    }
+}
+
+impl<S: FrameSource> VideoFrame for MatFrame<S> {
+    fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn bit_depth(&self) -> u8 {
+        8
+    }
+}
@@ -0,0 +1,1251 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use bytes::Bytes;
+use flowly::{
+    DataFrame, EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, Service, VideoFrame,
+};
+use futures::Stream;
+use openh264::encoder::{BitRate, Encoder, EncoderConfig, FrameRate, FrameType};
+use openh264_sys2::{
+    ENCODER_OPTION, ENCODER_OPTION_BITRATE, ENCODER_OPTION_FRAME_RATE, ENCODER_OPTION_MAX_BITRATE,
+    SBitrateInfo, SPATIAL_LAYER_ALL,
+};
+
+use crate::{
+    CodecConfig, Error, Library, LossReport, SeiSchedule, TimecodeSource, UserDataSei,
+    ltr::{self, Ltr, Recovery},
+    nal::{self, NalType},
+    sei::{self, PIC_TIMING, USER_DATA_UNREGISTERED},
+    timecode,
+    yuv::{self, Yuv420},
+};
+
+/// Layout of raw frames fed to an [`Openh264Encoder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Packed 8-bit RGB, as produced by [`Openh264Decoder`](crate::Openh264Decoder).
+    /// Converted with full range BT.601, like the decoder converts back by default.
+    Rgb8,
+    /// 8-bit planar YUV 4:2:0.
+    I420,
+    /// 8-bit planar YUV 4:2:2, chroma is averaged over row pairs down to 4:2:0.
+    I422,
+    /// 8-bit planar YUV 4:4:4, chroma is averaged over 2x2 blocks down to 4:2:0.
+    I444,
+}
+
+/// How [`EncodedH264Frame`] splits an access unit into chunks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Chunking {
+    /// A single contiguous Annex B access unit, as file muxers expect.
+    #[default]
+    AccessUnit,
+    /// One NAL unit per chunk without start codes, as RTP packetizers expect.
+    NalUnits,
+}
+
+/// Where the encoder takes the input frame rate from for rate control.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameRateMode {
+    /// The rate declared with [`EncoderOptions::frame_rate`].
+    Fixed,
+    /// Derived from the spacing of input timestamps, for variable frame rate
+    /// capture. `timescale` is the number of timestamp ticks per second; the
+    /// declared rate is used until two frames have been seen.
+    Timestamps { timescale: u64 },
+}
+
+/// Rectangle of the input picture to encode, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Crop {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// One output rendition of a simulcast encoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulcastLayer {
+    pub rid: Arc<str>,
+    pub width: u16,
+    pub height: u16,
+    pub bitrate: u32,
+}
+
+impl SimulcastLayer {
+    pub fn new(rid: impl Into<Arc<str>>, width: u16, height: u16, bitrate: u32) -> Self {
+        Self {
+            rid: rid.into(),
+            width,
+            height,
+            bitrate,
+        }
+    }
+}
+
+/// Configuration of an [`Openh264Encoder`].
+#[derive(Debug, Clone)]
+pub struct EncoderOptions {
+    pub(crate) library: Library,
+    pub(crate) bitrate: u32,
+    pub(crate) frame_rate: f32,
+    pub(crate) frame_rate_mode: FrameRateMode,
+    pub(crate) input_format: PixelFormat,
+    pub(crate) input_strides: Option<(usize, usize)>,
+    pub(crate) crop: Option<Crop>,
+    pub(crate) layers: Vec<SimulcastLayer>,
+    pub(crate) max_bitrate: Option<u32>,
+    pub(crate) vbv_buffer_size: Option<u32>,
+    pub(crate) chunking: Chunking,
+    pub(crate) sei: Vec<(UserDataSei, SeiSchedule)>,
+    pub(crate) timecode: Option<TimecodeSource>,
+    pub(crate) access_unit_delimiters: bool,
+    pub(crate) filler: bool,
+    pub(crate) expected_resolution: Option<(u16, u16)>,
+    pub(crate) long_term_references: Option<u32>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            library: Library::Source,
+            bitrate: 2_000_000,
+            frame_rate: 30.0,
+            frame_rate_mode: FrameRateMode::Fixed,
+            input_format: PixelFormat::Rgb8,
+            input_strides: None,
+            crop: None,
+            layers: Vec::new(),
+            max_bitrate: None,
+            vbv_buffer_size: None,
+            chunking: Chunking::AccessUnit,
+            sei: Vec::new(),
+            timecode: None,
+            access_unit_delimiters: false,
+            filler: false,
+            expected_resolution: None,
+            long_term_references: None,
+        }
+    }
+}
+
+impl EncoderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where to take openh264 from. Defaults to [`Library::Source`].
+    pub fn library(mut self, library: Library) -> Self {
+        self.library = library;
+        self
+    }
+
+    /// Target bitrate in bits per second, used when no simulcast layers are configured.
+    pub fn bitrate(mut self, bps: u32) -> Self {
+        self.bitrate = bps;
+        self
+    }
+
+    /// Declared input frame rate. Defaults to 30.
+    pub fn frame_rate(mut self, fps: f32) -> Self {
+        self.frame_rate = fps;
+        self
+    }
+
+    pub fn frame_rate_mode(mut self, mode: FrameRateMode) -> Self {
+        self.frame_rate_mode = mode;
+        self
+    }
+
+    pub fn input_format(mut self, format: PixelFormat) -> Self {
+        self.input_format = format;
+        self
+    }
+
+    /// Row strides in bytes of raw input whose rows are padded, as delivered by
+    /// many capture APIs and GPU readbacks: `luma` applies to the Y plane or to
+    /// RGB rows, `chroma` to the U and V planes. Planes are used in place
+    /// without repacking. Defaults to tightly packed rows.
+    ///
+    /// Planar input may come as three chunks, one per plane, or as a single
+    /// buffer with the planes following each other.
+    pub fn input_strides(mut self, luma: usize, chroma: usize) -> Self {
+        self.input_strides = Some((luma, chroma));
+        self
+    }
+
+    /// Encode only this part of every input frame, e.g. to strip letterboxing
+    /// or a sensor's dead border. The rectangle is widened to even
+    /// coordinates and clipped to the frame; simulcast layers scale the
+    /// cropped picture.
+    pub fn crop(mut self, crop: Crop) -> Self {
+        self.crop = Some(crop);
+        self
+    }
+
+    /// Adds a simulcast layer. With at least one layer configured every input
+    /// frame is scaled to and encoded at each layer's resolution and bitrate.
+    pub fn layer(mut self, layer: SimulcastLayer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Peak bitrate in bits per second that openh264's rate control must not
+    /// exceed, skipping frames if needed. With simulcast the cap is split across
+    /// layers in proportion to their bitrates.
+    pub fn max_bitrate(mut self, bps: u32) -> Self {
+        self.max_bitrate = Some(bps);
+        self
+    }
+
+    /// Size in bits of the decoder buffer the output must fit, drained at
+    /// [`max_bitrate`](Self::max_bitrate) (or the target bitrate if unset).
+    /// Input frames are skipped while the modeled buffer is full.
+    pub fn vbv_buffer_size(mut self, bits: u32) -> Self {
+        self.vbv_buffer_size = Some(bits);
+        self
+    }
+
+    /// Layout of the chunks of output frames. [`data`](EncodedH264Frame::data)
+    /// always holds the whole access unit.
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Attaches a user data unregistered SEI message to every frame matching
+    /// `schedule`. One-off messages go through [`EncoderControl::attach_sei`].
+    pub fn sei(mut self, sei: UserDataSei, schedule: SeiSchedule) -> Self {
+        self.sei.push((sei, schedule));
+        self
+    }
+
+    /// Emits a picture timing SEI with a timecode on every frame. The SPS is
+    /// rewritten to signal `pic_struct_present_flag` so decoders pick it up.
+    pub fn timecode(mut self, source: TimecodeSource) -> Self {
+        self.timecode = Some(source);
+        self
+    }
+
+    /// Starts every access unit with an access unit delimiter, as MPEG-TS
+    /// packagers and some hardware decoders require.
+    pub fn access_unit_delimiters(mut self, enabled: bool) -> Self {
+        self.access_unit_delimiters = enabled;
+        self
+    }
+
+    /// Pads access units with filler data NAL units whenever the output falls
+    /// below the target bitrate, for links and muxes that need a steady rate.
+    /// See [`EncoderControl::filler_stats`] for the overhead.
+    pub fn filler(mut self, enabled: bool) -> Self {
+        self.filler = enabled;
+        self
+    }
+
+    /// Resolution of the input frames, if known up front. The library is then
+    /// loaded, the openh264 instances created and the RGB conversion buffers
+    /// allocated at construction instead of on the first frame. openh264
+    /// still sets up its own encoding state on the first frame.
+    pub fn expected_resolution(mut self, width: u16, height: u16) -> Self {
+        self.expected_resolution = Some((width, height));
+        self
+    }
+
+    /// Keep `refs` long-term reference frames, so
+    /// [`EncoderControl::report_loss`] can recover from a loss by referencing
+    /// the last frame the receiver got instead of sending an IDR. Recovery
+    /// falls back to an IDR whenever openh264 cannot use them.
+    pub fn long_term_references(mut self, refs: u32) -> Self {
+        self.long_term_references = Some(refs);
+        self
+    }
+}
+
+/// Encoder parameters that can be changed while the encoder is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderSettings {
+    /// Total target bitrate in bits per second, split across simulcast layers
+    /// in proportion to their configured bitrates.
+    pub bitrate: u32,
+    pub frame_rate: f32,
+    /// Output resolution, `None` to encode at input size. Ignored with simulcast layers.
+    pub resolution: Option<(u16, u16)>,
+}
+
+/// Filler data added by an encoder with [`EncoderOptions::filler`], over all layers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FillerStats {
+    pub frames: u64,
+    pub padded_frames: u64,
+    /// Bytes of filler NAL units, start codes included.
+    pub filler_bytes: u64,
+    /// All output bytes, filler included.
+    pub total_bytes: u64,
+}
+
+impl FillerStats {
+    /// Share of the output that is filler, between 0 and 1.
+    pub fn overhead(&self) -> f64 {
+        self.filler_bytes as f64 / self.total_bytes.max(1) as f64
+    }
+}
+
+/// Handle for changing the settings of a running [`Openh264Encoder`].
+///
+/// Changes are picked up on the next frame. Bitrate and frame rate are
+/// applied to the running openh264 instances, a new resolution restarts them
+/// and so starts a new GOP.
+#[derive(Debug, Clone)]
+pub struct EncoderControl {
+    inner: Arc<Mutex<(u64, EncoderSettings)>>,
+    sei: Arc<Mutex<Vec<UserDataSei>>>,
+    filler: Arc<Mutex<FillerStats>>,
+    keyframe: Arc<AtomicBool>,
+    loss: Arc<Mutex<Vec<LossReport>>>,
+}
+
+impl EncoderControl {
+    fn new(settings: EncoderSettings) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new((0, settings))),
+            sei: Default::default(),
+            filler: Default::default(),
+            keyframe: Default::default(),
+            loss: Default::default(),
+        }
+    }
+
+    pub fn filler_stats(&self) -> FillerStats {
+        *self.filler.lock().unwrap()
+    }
+
+    fn record_filler(&self, filler: usize, total: usize) {
+        let mut stats = self.filler.lock().unwrap();
+
+        stats.frames += 1;
+        stats.padded_frames += (filler > 0) as u64;
+        stats.filler_bytes += filler as u64;
+        stats.total_bytes += total as u64;
+    }
+
+    /// Queues a user data unregistered SEI message for the next encoded frame
+    /// of every layer. Does not restart the encoder.
+    pub fn attach_sei(&self, sei: UserDataSei) {
+        self.sei.lock().unwrap().push(sei);
+    }
+
+    fn take_sei(&self) -> Vec<UserDataSei> {
+        std::mem::take(&mut *self.sei.lock().unwrap())
+    }
+
+    /// Makes the next encoded frame of every layer an IDR frame, e.g. after a
+    /// decoder reported [`DecoderEvent::KeyframeRequested`](crate::DecoderEvent::KeyframeRequested).
+    /// Does not restart the encoder.
+    pub fn request_keyframe(&self) {
+        self.keyframe.store(true, Ordering::Relaxed);
+    }
+
+    fn take_keyframe_request(&self) -> bool {
+        self.keyframe.swap(false, Ordering::Relaxed)
+    }
+
+    /// Handles receiver feedback on the next frame of every layer: nothing
+    /// if an IDR went out after the loss, an LTR recovery frame with
+    /// [`long_term_references`](EncoderOptions::long_term_references) and a
+    /// known last good frame, an IDR otherwise.
+    pub fn report_loss(&self, report: LossReport) {
+        self.loss.lock().unwrap().push(report);
+    }
+
+    fn take_loss_reports(&self) -> Vec<LossReport> {
+        std::mem::take(&mut *self.loss.lock().unwrap())
+    }
+
+    pub fn settings(&self) -> EncoderSettings {
+        self.inner.lock().unwrap().1
+    }
+
+    pub fn update(&self, settings: EncoderSettings) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.1 != settings {
+            inner.0 += 1;
+            inner.1 = settings;
+        }
+    }
+
+    /// Returns the settings if they changed since generation `seen`.
+    fn changed(&self, seen: &mut u64) -> Option<EncoderSettings> {
+        let inner = self.inner.lock().unwrap();
+
+        if inner.0 == *seen {
+            return None;
+        }
+
+        *seen = inner.0;
+
+        Some(inner.1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodedH264Frame<S> {
+    pub timestamp: u64,
+    pub data: Bytes,
+    pub width: u16,
+    pub height: u16,
+    pub flags: FrameFlags,
+    /// Index of the simulcast layer this frame belongs to, `0` without simulcast.
+    pub layer: usize,
+    /// Identifier of the simulcast layer, if any.
+    pub rid: Option<Arc<str>>,
+    /// Set on IDR frames, where a new GOP and thus a new segment may start.
+    pub gop_start: bool,
+    /// Parameter sets carried by a [`gop_start`](Self::gop_start) frame.
+    pub codec_config: Option<Arc<CodecConfig>>,
+    chunking: Chunking,
+    source: S,
+}
+
+impl<S: FrameSource> DataFrame for EncodedH264Frame<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        let chunks: Vec<_> = match self.chunking {
+            Chunking::AccessUnit => vec![&self.data[..]],
+            Chunking::NalUnits => nal::nal_units(&self.data).collect(),
+        };
+
+        chunks.into_iter()
+    }
+
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        let chunks: Vec<_> = match self.chunking {
+            Chunking::AccessUnit => vec![self.data],
+            Chunking::NalUnits => nal::nal_units(&self.data)
+                .map(|unit| self.data.slice_ref(unit))
+                .collect(),
+        };
+
+        chunks.into_iter()
+    }
+}
+
+impl<S: FrameSource> Frame for EncodedH264Frame<S> {
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn codec(&self) -> Fourcc {
+        Fourcc::VIDEO_H264
+    }
+
+    fn flags(&self) -> FrameFlags {
+        self.flags
+    }
+}
+
+impl<S: FrameSource> VideoFrame for EncodedH264Frame<S> {
+    fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn bit_depth(&self) -> u8 {
+        8
+    }
+}
+
+impl<S: FrameSource> EncodedFrame for EncodedH264Frame<S> {}
+
+/// H.264 encoder service turning raw video frames into Annex B access units.
+///
+/// Encoding happens inline in [`Service::handle`]. Each openh264 instance is
+/// created on the first frame and recreated whenever the input resolution changes.
+pub struct Openh264Encoder {
+    options: EncoderOptions,
+    layers: Vec<LayerEncoder>,
+    control: EncoderControl,
+    generation: u64,
+    rate: RateEstimator,
+    last_timestamp: Option<u64>,
+    /// Added to input timestamps since they last went backwards.
+    timestamp_offset: u64,
+    frame_index: u64,
+    /// Planes of the last RGB conversion, reused by the next one.
+    scratch: Option<Yuv420>,
+}
+
+/// Running average of the interval between raw input timestamps, skipping
+/// steps that do not move forward.
+#[derive(Default)]
+struct RateEstimator {
+    last: Option<u64>,
+    interval: Option<f64>,
+}
+
+impl RateEstimator {
+    fn update(&mut self, timestamp: u64, timescale: u64) -> Option<f32> {
+        if let Some(last) = self.last.replace(timestamp) {
+            if timestamp > last {
+                let dt = (timestamp - last) as f64 / timescale.max(1) as f64;
+
+                self.interval = Some(self.interval.map_or(dt, |avg| avg * 0.9 + dt * 0.1));
+            }
+        }
+
+        self.interval.map(|avg| (1.0 / avg) as f32)
+    }
+}
+
+/// openh264 instance producing a single rendition.
+struct LayerEncoder {
+    library: Library,
+    rid: Option<Arc<str>>,
+    bitrate: u32,
+    /// Fixed output size of a simulcast layer or a runtime override, input size otherwise.
+    size: Option<(usize, usize)>,
+    max_bitrate: Option<u32>,
+    vbv: Option<Vbv>,
+    encoder: Option<(Encoder, (usize, usize))>,
+    /// Whether the openh264 instance encoded a frame, which is when it is
+    /// initialized from its config and starts accepting options.
+    initialized: bool,
+    /// Frame rate the current openh264 instance is configured with.
+    frame_rate: f32,
+    /// Bytes the output is below the target bitrate, negative when above.
+    filler_credit: f64,
+    ltr: Option<Ltr>,
+}
+
+/// Leaky bucket filled with encoded bits and drained at the channel rate once
+/// per frame; while it is over `size` the stream would underflow a compliant decoder.
+struct Vbv {
+    size: f64,
+    rate: f64,
+    fullness: f64,
+}
+
+impl Vbv {
+    fn new(size: u32, rate: u32) -> Self {
+        Self {
+            size: size as f64,
+            rate: rate as f64,
+            fullness: 0.0,
+        }
+    }
+
+    /// Drains one frame interval, returns whether the next frame may be encoded.
+    fn admit(&mut self, frame_rate: f32) -> bool {
+        self.fullness = (self.fullness - self.rate / frame_rate.max(1.0) as f64).max(0.0);
+        self.fullness < self.size
+    }
+
+    fn fill(&mut self, bytes: usize) {
+        self.fullness += bytes as f64 * 8.0;
+    }
+}
+
+impl Openh264Encoder {
+    pub fn new(options: EncoderOptions) -> Self {
+        let mut layers: Vec<_> = if options.layers.is_empty() {
+            vec![LayerEncoder {
+                library: options.library.clone(),
+                rid: None,
+                bitrate: options.bitrate,
+                size: None,
+                max_bitrate: None,
+                vbv: None,
+                encoder: None,
+                initialized: false,
+                frame_rate: options.frame_rate,
+                filler_credit: 0.0,
+                ltr: options.long_term_references.map(Ltr::new),
+            }]
+        } else {
+            options
+                .layers
+                .iter()
+                .map(|layer| LayerEncoder {
+                    library: options.library.clone(),
+                    rid: Some(layer.rid.clone()),
+                    bitrate: layer.bitrate,
+                    // openh264 only encodes even dimensions
+                    size: Some((layer.width as usize & !1, layer.height as usize & !1)),
+                    max_bitrate: None,
+                    vbv: None,
+                    encoder: None,
+                    initialized: false,
+                    frame_rate: options.frame_rate,
+                    filler_credit: 0.0,
+                    ltr: options.long_term_references.map(Ltr::new),
+                })
+                .collect()
+        };
+
+        let total = layers.iter().map(|l| l.bitrate as u64).sum::<u64>().max(1);
+
+        for layer in &mut layers {
+            let share = layer.bitrate;
+
+            layer.max_bitrate = options.max_bitrate.map(|max| proportion(max, share, total));
+
+            if let Some(max) = layer.max_bitrate {
+                layer.bitrate = layer.bitrate.min(max);
+            }
+
+            layer.vbv = options.vbv_buffer_size.map(|size| {
+                Vbv::new(
+                    proportion(size, share, total),
+                    layer.max_bitrate.unwrap_or(layer.bitrate),
+                )
+            });
+        }
+
+        let mut scratch = None;
+
+        if let Some((width, height)) = options.expected_resolution {
+            let input = (width as usize, height as usize);
+            let encoded = options
+                .crop
+                .and_then(|crop| {
+                    let rect = (
+                        crop.x as usize,
+                        crop.y as usize,
+                        crop.width as usize,
+                        crop.height as usize,
+                    );
+
+                    yuv::crop_rect(rect, input)
+                })
+                .map_or(input, |(_, _, width, height)| (width, height));
+
+            for layer in &mut layers {
+                let size = layer.size.unwrap_or(encoded);
+
+                match layer.build(options.frame_rate) {
+                    Ok(encoder) => layer.encoder = Some((encoder, size)),
+                    Err(err) => log::warn!("failed to set up openh264 encoder up front: {err}"),
+                }
+            }
+
+            if options.input_format == PixelFormat::Rgb8 {
+                scratch = Some(Yuv420::blank(input.0, input.1));
+            }
+        }
+
+        let control = EncoderControl::new(EncoderSettings {
+            bitrate: layers.iter().map(|layer| layer.bitrate).sum(),
+            frame_rate: options.frame_rate,
+            resolution: None,
+        });
+
+        Self {
+            options,
+            layers,
+            control,
+            generation: 0,
+            rate: RateEstimator::default(),
+            last_timestamp: None,
+            timestamp_offset: 0,
+            frame_index: 0,
+            scratch,
+        }
+    }
+
+    /// Handle for changing bitrate, frame rate and resolution at runtime.
+    pub fn control(&self) -> EncoderControl {
+        self.control.clone()
+    }
+
+    fn apply_control(&mut self) {
+        let Some(settings) = self.control.changed(&mut self.generation) else {
+            return;
+        };
+
+        let configured: Vec<u32> = if self.options.layers.is_empty() {
+            vec![self.options.bitrate]
+        } else {
+            self.options.layers.iter().map(|l| l.bitrate).collect()
+        };
+
+        let total = configured.iter().map(|&b| b as u64).sum::<u64>().max(1);
+
+        // an estimated input rate the encoder follows is only overridden by a new setting
+        let frame_rate =
+            (settings.frame_rate != self.options.frame_rate).then_some(settings.frame_rate);
+        self.options.frame_rate = settings.frame_rate;
+
+        for (layer, configured) in self.layers.iter_mut().zip(configured) {
+            let bitrate = proportion(settings.bitrate, configured, total);
+
+            let bitrate = layer.max_bitrate.map_or(bitrate, |max| bitrate.min(max));
+
+            layer.retarget(bitrate, frame_rate);
+        }
+
+        // a new size rebuilds the encoder on the next frame, starting with an IDR
+        if self.options.layers.is_empty() {
+            self.layers[0].size = settings
+                .resolution
+                .map(|(w, h)| (w as usize & !1, h as usize & !1));
+        }
+    }
+
+    /// openh264 emits no B-frames, so output goes out in input order with DTS
+    /// equal to PTS; input that goes backwards (e.g. after a decoder restart)
+    /// is shifted forward from then on, keeping its spacing, so muxers always
+    /// see increasing timestamps.
+    fn monotonic(&mut self, timestamp: u64) -> u64 {
+        let adjusted = timestamp.saturating_add(self.timestamp_offset);
+
+        let timestamp = match self.last_timestamp {
+            Some(last) if adjusted <= last => {
+                log::debug!("non-monotonic input timestamp {timestamp}, shifting input after it");
+                self.timestamp_offset += last + 1 - adjusted;
+                last + 1
+            }
+            _ => adjusted,
+        };
+
+        self.last_timestamp = Some(timestamp);
+
+        timestamp
+    }
+
+    fn frame_rate(&mut self, timestamp: u64) -> f32 {
+        match self.options.frame_rate_mode {
+            FrameRateMode::Fixed => self.options.frame_rate,
+            FrameRateMode::Timestamps { timescale } => self
+                .rate
+                .update(timestamp, timescale)
+                .unwrap_or(self.options.frame_rate),
+        }
+    }
+
+    fn encode<S: Clone>(
+        &mut self,
+        picture: &Yuv420,
+        timestamp: u64,
+        frame_rate: f32,
+        source: &S,
+    ) -> Vec<Result<EncodedH264Frame<S>, Error>> {
+        let mut out = Vec::with_capacity(self.layers.len());
+        let attached = self.control.take_sei();
+        let force_keyframe = self.control.take_keyframe_request();
+        let losses = self.control.take_loss_reports();
+        let frame_index = self.frame_index;
+
+        self.frame_index += 1;
+
+        for (idx, layer) in self.layers.iter_mut().enumerate() {
+            let scaled;
+            let picture = match layer.size {
+                Some((width, height)) if (width, height) != picture.size() => {
+                    scaled = picture.scale(width, height);
+                    &scaled
+                }
+                _ => picture,
+            };
+
+            let force_keyframe = layer.recover(&losses) || force_keyframe;
+
+            match layer.encode(picture, frame_rate, force_keyframe) {
+                Ok(Some((data, keyframe, idr))) => {
+                    if let Some(ltr) = &mut layer.ltr {
+                        ltr.track(timestamp, &data);
+                    }
+
+                    let (width, height) = picture.size();
+                    let mut flags = FrameFlags::VIDEO_STREAM;
+
+                    if keyframe {
+                        flags |= FrameFlags::KEYFRAME;
+                    }
+
+                    let info = FrameInfo {
+                        index: frame_index,
+                        timestamp,
+                        frame_rate,
+                        keyframe,
+                    };
+
+                    let mut data = decorate(&self.options, &attached, &info, data);
+
+                    if self.options.filler {
+                        let (padded, filler) = layer.pad(data, frame_rate);
+
+                        self.control.record_filler(filler, padded.len());
+                        data = padded;
+                    }
+
+                    out.push(Ok(EncodedH264Frame {
+                        timestamp,
+                        data,
+                        width: width as _,
+                        height: height as _,
+                        flags,
+                        layer: idx,
+                        rid: layer.rid.clone(),
+                        gop_start: idr,
+                        codec_config: idr
+                            .then(|| CodecConfig::from_access_unit(&data))
+                            .flatten()
+                            .map(Arc::new),
+                        chunking: self.options.chunking,
+                        source: source.clone(),
+                    }));
+                }
+                Ok(None) => (),
+                Err(err) => out.push(Err(err)),
+            }
+        }
+
+        out
+    }
+}
+
+impl LayerEncoder {
+    /// Returns the encoded access unit and whether it is a keyframe and an IDR,
+    /// `None` for skipped frames.
+    fn encode(
+        &mut self,
+        picture: &Yuv420,
+        frame_rate: f32,
+        force_keyframe: bool,
+    ) -> Result<Option<(Bytes, bool, bool)>, Error> {
+        if let Some(vbv) = &mut self.vbv {
+            if !vbv.admit(frame_rate) {
+                log::debug!("vbv buffer full, skipping frame");
+                return Ok(None);
+            }
+        }
+
+        let size = picture.size();
+
+        let (encoder, _) = match self.encoder.take() {
+            Some(mut built) if built.1 == size => {
+                // follow drifting input rates without restarting the encoder
+                if (frame_rate - self.frame_rate).abs() > self.frame_rate * 0.1 {
+                    if self.initialized {
+                        set_frame_rate(&mut built.0, frame_rate)?;
+                        self.frame_rate = frame_rate;
+                    } else {
+                        built.0 = self.build(frame_rate)?;
+                    }
+                }
+
+                self.encoder.insert(built)
+            }
+            _ => {
+                let encoder = self.build(frame_rate)?;
+
+                self.encoder.insert((encoder, size))
+            }
+        };
+
+        if force_keyframe {
+            encoder.force_intra_frame();
+        }
+
+        let bitstream = encoder.encode(picture)?;
+        let frame_type = bitstream.frame_type();
+        let data = bitstream.to_vec();
+
+        if !self.initialized {
+            self.initialized = true;
+            self.configure()?;
+        }
+
+        let (keyframe, idr) = match frame_type {
+            FrameType::Skip | FrameType::Invalid => return Ok(None),
+            FrameType::IDR => (true, true),
+            FrameType::I => (true, false),
+            _ => (false, false),
+        };
+
+        if let Some(vbv) = &mut self.vbv {
+            vbv.fill(data.len());
+        }
+
+        Ok(Some((data.into(), keyframe, idr)))
+    }
+
+    /// Creates an openh264 instance with the layer's current settings.
+    fn build(&mut self, frame_rate: f32) -> Result<Encoder, Error> {
+        self.frame_rate = frame_rate;
+        self.initialized = false;
+
+        let mut config = EncoderConfig::new()
+            .bitrate(BitRate::from_bps(self.bitrate))
+            .max_frame_rate(FrameRate::from_hz(frame_rate));
+
+        if self.max_bitrate.is_some() {
+            config = config.skip_frames(true);
+        }
+
+        let encoder = Encoder::with_api_config(self.library.load()?, config)?;
+
+        if let Some(ltr) = &mut self.ltr {
+            ltr.reset();
+        }
+
+        Ok(encoder)
+    }
+
+    /// Applies the options openh264 only keeps once initialized, which
+    /// happens on the first frame and rewrites everything set before from the
+    /// config. That first frame, an IDR, is not capped yet.
+    fn configure(&mut self) -> Result<(), Error> {
+        let Some((encoder, _)) = &mut self.encoder else {
+            return Ok(());
+        };
+
+        if let Some(ltr) = &self.ltr {
+            if let Err(err) = ltr::enable(encoder, ltr.refs) {
+                log::warn!("long-term references unavailable, recovering with IDRs: {err}");
+                self.ltr = None;
+            }
+        }
+
+        if let Some(max) = self.max_bitrate {
+            set_bitrate_option(encoder, ENCODER_OPTION_MAX_BITRATE, "max bitrate", max)?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes bitrate and frame rate in place, so the stream goes on without an IDR.
+    fn retarget(&mut self, bitrate: u32, frame_rate: Option<f32>) {
+        if bitrate == self.bitrate && frame_rate.is_none() {
+            return;
+        }
+
+        self.bitrate = bitrate;
+
+        // one that encoded nothing yet is rebuilt from the new config for free
+        let Some((encoder, _)) = self.encoder.as_mut().filter(|_| self.initialized) else {
+            self.encoder = None;
+            return;
+        };
+
+        let res = set_bitrate_option(encoder, ENCODER_OPTION_BITRATE, "bitrate", bitrate)
+            .and_then(|()| frame_rate.map_or(Ok(()), |fps| set_frame_rate(encoder, fps)));
+
+        match res {
+            Ok(()) => self.frame_rate = frame_rate.unwrap_or(self.frame_rate),
+            Err(err) => {
+                log::debug!("rebuilding encoder for new settings: {err}");
+                self.encoder = None;
+            }
+        }
+    }
+}
+
+impl LayerEncoder {
+    /// Answers loss reports, returns whether the next frame has to be an IDR.
+    fn recover(&mut self, reports: &[LossReport]) -> bool {
+        let Some(report) = reports.iter().min_by_key(|report| report.lost) else {
+            return false;
+        };
+
+        // a new openh264 instance starts with an IDR anyway
+        let Some((encoder, _)) = self.encoder.as_mut().filter(|_| self.initialized) else {
+            return false;
+        };
+
+        let Some(ltr) = &self.ltr else {
+            return true;
+        };
+
+        match ltr.recovery(report) {
+            Recovery::None => false,
+            Recovery::Ltr {
+                idr_pic_id,
+                last_good,
+                current,
+            } => match ltr::request_recovery(encoder, idr_pic_id, last_good, current) {
+                Ok(()) => false,
+                Err(err) => {
+                    log::debug!("falling back to an IDR after frame {}: {err}", report.lost);
+                    true
+                }
+            },
+            Recovery::Idr => true,
+        }
+    }
+
+    /// Appends filler data to bring the layer up to its target bitrate,
+    /// returning the padded access unit and the bytes added.
+    fn pad(&mut self, data: Bytes, frame_rate: f32) -> (Bytes, usize) {
+        // start code, NAL header and rbsp trailing byte
+        const OVERHEAD: usize = 6;
+
+        let target = self.bitrate as f64 / 8.0 / frame_rate.max(1.0) as f64;
+
+        // an overshoot is paid back within a second, not held against the stream forever
+        let floor = -target * frame_rate.max(1.0) as f64;
+        self.filler_credit = (self.filler_credit + target - data.len() as f64).max(floor);
+
+        if self.filler_credit < OVERHEAD as f64 {
+            return (data, 0);
+        }
+
+        let filler = self.filler_credit as usize;
+        self.filler_credit -= filler as f64;
+
+        let mut unit = vec![0xff; filler - 4];
+        unit[0] = 0x0c;
+        *unit.last_mut().unwrap() = 0x80;
+
+        (nal::append(&data, &unit), filler)
+    }
+}
+
+/// What [`decorate`] needs to know about the frame an access unit encodes.
+struct FrameInfo {
+    index: u64,
+    timestamp: u64,
+    frame_rate: f32,
+    keyframe: bool,
+}
+
+/// Adds the NAL units requested by the options to an encoded access unit.
+fn decorate(
+    options: &EncoderOptions,
+    attached: &[UserDataSei],
+    info: &FrameInfo,
+    mut data: Bytes,
+) -> Bytes {
+    let mut messages: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    if let Some(source) = &options.timecode {
+        data = nal::map_units(&data, |unit| match NalType::from_header(unit[0]) {
+            NalType::Sps => timecode::with_pic_struct(unit).or_else(|| {
+                log::warn!("sps not rewritable for picture timing, timecodes may be ignored");
+                None
+            }),
+            _ => None,
+        });
+
+        let timecode = source.timecode(info.timestamp, info.frame_rate);
+        messages.push((PIC_TIMING, timecode.pic_timing_payload()));
+    }
+
+    let user_data = attached.iter().chain(
+        options
+            .sei
+            .iter()
+            .filter(|(_, schedule)| schedule.matches(info.index, info.keyframe))
+            .map(|(sei, _)| sei),
+    );
+
+    messages.extend(user_data.map(|sei| (USER_DATA_UNREGISTERED, sei::user_data_payload(sei))));
+
+    if !messages.is_empty() {
+        let sei = sei::sei_nal(
+            messages
+                .iter()
+                .map(|(payload_type, payload)| (*payload_type, &payload[..])),
+        );
+
+        data = nal::insert_before_vcl(&data, &[sei]);
+    }
+
+    if options.access_unit_delimiters {
+        // primary_pic_type 0 (I slices only) or 1 (I and P), openh264 has no B slices
+        let primary_pic_type = if info.keyframe { 0 } else { 1 };
+
+        data = nal::prepend(&data, &[0x09, primary_pic_type << 5 | 0x10]);
+    }
+
+    data
+}
+
+/// Sets `ENCODER_OPTION_BITRATE` or `ENCODER_OPTION_MAX_BITRATE` for all layers.
+fn set_bitrate_option(
+    encoder: &mut Encoder,
+    option: ENCODER_OPTION,
+    name: &'static str,
+    bps: u32,
+) -> Result<(), Error> {
+    let mut info = SBitrateInfo {
+        iLayer: SPATIAL_LAYER_ALL,
+        iBitrate: bps as _,
+    };
+
+    let code = unsafe {
+        encoder
+            .raw_api()
+            .set_option(option, std::ptr::addr_of_mut!(info).cast())
+    };
+
+    if code != 0 {
+        return Err(Error::EncoderOption(name, code as _));
+    }
+
+    Ok(())
+}
+
+fn set_frame_rate(encoder: &mut Encoder, fps: f32) -> Result<(), Error> {
+    let mut fps = fps;
+
+    let code = unsafe {
+        encoder.raw_api().set_option(
+            ENCODER_OPTION_FRAME_RATE,
+            std::ptr::addr_of_mut!(fps).cast(),
+        )
+    };
+
+    if code != 0 {
+        return Err(Error::EncoderOption("frame rate", code as _));
+    }
+
+    Ok(())
+}
+
+/// `value * part / total`, for splitting totals across simulcast layers.
+#[inline]
+fn proportion(value: u32, part: u32, total: u64) -> u32 {
+    (value as u64 * part as u64 / total) as u32
+}
+
+impl Default for Openh264Encoder {
+    fn default() -> Self {
+        Self::new(EncoderOptions::default())
+    }
+}
+
+impl<F: VideoFrame + 'static> Service<F> for Openh264Encoder {
+    type Out = Result<EncodedH264Frame<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            // spacing of the input as is, shifts after backwards jumps would distort it
+            let frame_rate = self.frame_rate(frame.timestamp());
+            let ts = self.monotonic(frame.timestamp());
+            let source = frame.source().clone();
+
+            self.apply_control();
+
+            match to_picture(&self.options, frame, self.scratch.take()) {
+                Ok(picture) => {
+                    for res in self.encode(&picture, ts, frame_rate, &source) {
+                        yield res;
+                    }
+
+                    // planar input wraps the caller's buffers, only RGB conversions are reusable
+                    if self.options.input_format == PixelFormat::Rgb8 {
+                        self.scratch = Some(picture);
+                    }
+                }
+                Err(err) => yield Err(err),
+            }
+        }
+    }
+}
+
+/// Converts an input frame, RGB into the planes of `scratch` where possible.
+fn to_picture<F: VideoFrame>(
+    options: &EncoderOptions,
+    frame: F,
+    scratch: Option<Yuv420>,
+) -> Result<Yuv420, Error> {
+    let picture = to_full_picture(options, frame, scratch)?;
+
+    let Some(crop) = options.crop else {
+        return Ok(picture);
+    };
+
+    picture
+        .crop(crop.x as _, crop.y as _, crop.width as _, crop.height as _)
+        .ok_or(Error::InvalidCrop(crop))
+}
+
+fn to_full_picture<F: VideoFrame>(
+    options: &EncoderOptions,
+    frame: F,
+    scratch: Option<Yuv420>,
+) -> Result<Yuv420, Error> {
+    let (width, height) = frame.dimensions();
+    let (width, height) = (width as usize, height as usize);
+    let format = options.input_format;
+
+    if format == PixelFormat::Rgb8 && frame.codec() != Fourcc::PIXEL_FORMAT_RGB888 {
+        return Err(Error::UnsupportedPixelFormat(frame.codec()));
+    }
+
+    let mut chunks: Vec<Bytes> = frame.into_chunks().map(|c| c.into_cpu_bytes()).collect();
+
+    let (sub_x, sub_y) = match format {
+        PixelFormat::Rgb8 => {
+            let stride = options
+                .input_strides
+                .map_or(width * 3, |(stride, _)| stride);
+            let data = concat(chunks);
+
+            return Yuv420::from_rgb8_reusing(&data, width, height, stride, scratch)
+                .ok_or(Error::InvalidFrameSize(data.len()));
+        }
+        PixelFormat::I420 => (2, 2),
+        PixelFormat::I422 => (2, 1),
+        PixelFormat::I444 => (1, 1),
+    };
+
+    let (cw, ch) = (width.div_ceil(sub_x), height.div_ceil(sub_y));
+    let (luma, chroma) = options.input_strides.unwrap_or((width, cw));
+    let strides = (luma, chroma, chroma);
+
+    // three chunks are taken as separate planes, anything else as one buffer
+    let (planes, size) = if let [y, u, v] = &mut chunks[..] {
+        let size = y.len() + u.len() + v.len();
+
+        (
+            (std::mem::take(y), std::mem::take(u), std::mem::take(v)),
+            size,
+        )
+    } else {
+        let mut data = concat(chunks);
+        let size = data.len();
+        let y = data.split_to((luma * height).min(data.len()));
+        let u = data.split_to((chroma * ch).min(data.len()));
+
+        ((y, u, data), size)
+    };
+
+    let picture = if format == PixelFormat::I420 {
+        Yuv420::from_planes(width, height, strides, planes)
+    } else {
+        Yuv420::from_subsampled(width, height, strides, planes, (sub_x, sub_y))
+    };
+
+    picture.ok_or(Error::InvalidFrameSize(size))
+}
+
+/// Joins chunks into one buffer, without copying when there is only one.
+fn concat(mut chunks: Vec<Bytes>) -> Bytes {
+    if chunks.len() == 1 {
+        return chunks.pop().unwrap_or_default();
+    }
+
+    chunks.concat().into()
+}
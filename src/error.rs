@@ -10,4 +10,37 @@ pub enum Error {
 
     #[error("OpenH264 Decoder Failed (worker dead, cannot send)")]
     TrySendError,
+
+    #[error("Unsupported input pixel format: {0:?}")]
+    UnsupportedPixelFormat(flowly::Fourcc),
+
+    #[error("Raw frame data does not match its dimensions ({0} bytes)")]
+    InvalidFrameSize(usize),
+
+    #[error("Crop rectangle {0:?} lies outside of the input frame")]
+    InvalidCrop(crate::Crop),
+
+    #[error("OpenH264 Encoder rejected {0} option (code {1})")]
+    EncoderOption(&'static str, i32),
+
+    #[error("OpenH264 library not found, tried: {0}")]
+    LibraryNotFound(String),
+
+    #[error("Malformed access unit {0}: {1}")]
+    Malformed(u64, String),
+
+    #[error("Decoding the GOP starting at {0} failed at access unit {1}: {2}")]
+    GopDecode(u64, u64, #[source] Box<Error>),
+
+    #[cfg(feature = "opencv")]
+    #[error(transparent)]
+    OpenCv(#[from] opencv::Error),
+
+    #[cfg(feature = "webrtc")]
+    #[error("RTP depacketization failed: {0}")]
+    Rtp(#[from] webrtc::rtp::Error),
+
+    #[cfg(feature = "test-support")]
+    #[error("Invalid golden reference: {0}")]
+    InvalidReference(String),
 }
@@ -0,0 +1,408 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use flowly::Service;
+use futures::Stream;
+use openh264::formats::YUVSource;
+
+use crate::{
+    Bt601FullRange, ColorConvert, DecodedFrame, Error, FrameBuffer, YuvPlanes,
+    yuv::{self, Yuv420},
+};
+
+/// Reference pictures a [`GoldenCompare`] checks decoded frames against.
+#[derive(Debug, Clone)]
+pub enum GoldenSource {
+    /// A `.y4m` file with 8-bit 4:2:0 frames, matched to decoded frames in order.
+    Y4m(PathBuf),
+    /// A directory of binary PPM (`P6`) images, matched in file name order.
+    PpmDirectory(PathBuf),
+}
+
+/// How a decoded frame has to match its reference to pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    /// Bit-exact pixels, i.e. equal checksums.
+    Exact,
+    /// PSNR in dB of at least the given value.
+    Psnr(f64),
+}
+
+/// Verdict for one decoded frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GoldenResult {
+    /// Position of the frame in the decoded stream.
+    pub index: usize,
+    pub timestamp: u64,
+    pub passed: bool,
+    /// `None` if there was no reference frame left or the dimensions differ,
+    /// infinite for identical pictures.
+    pub psnr: Option<f64>,
+}
+
+/// Test-support service comparing decoded frames against golden references,
+/// for end-to-end validation of pipelines in CI.
+///
+/// Frames are compared as I420 against a y4m reference and as RGB against
+/// PPM references. Where the layouts differ, the I420 side is converted to
+/// RGB with the [`color_converter`](Self::color_converter), the way the
+/// decoder produces RGB output.
+pub struct GoldenCompare {
+    reference: Reference,
+    comparison: Comparison,
+    converter: Arc<dyn ColorConvert>,
+    index: usize,
+}
+
+enum Reference {
+    Y4m {
+        reader: BufReader<File>,
+        width: usize,
+        height: usize,
+    },
+    Ppm(std::vec::IntoIter<PathBuf>),
+}
+
+/// A reference or decoded picture in a layout both sides can be compared in.
+enum Picture {
+    Rgb(usize, usize, Vec<u8>),
+    I420(Yuv420),
+}
+
+impl GoldenCompare {
+    pub fn open(source: GoldenSource, comparison: Comparison) -> Result<Self, Error> {
+        let reference = match source {
+            GoldenSource::Y4m(path) => {
+                let mut reader = BufReader::new(File::open(path)?);
+                let (width, height) = read_y4m_header(&mut reader)?;
+
+                Reference::Y4m {
+                    reader,
+                    width,
+                    height,
+                }
+            }
+            GoldenSource::PpmDirectory(path) => {
+                let mut files = std::fs::read_dir(path)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                files.retain(|path| path.extension().is_some_and(|ext| ext == "ppm"));
+                files.sort();
+
+                Reference::Ppm(files.into_iter())
+            }
+        };
+
+        Ok(Self {
+            reference,
+            comparison,
+            converter: Arc::new(Bt601FullRange),
+            index: 0,
+        })
+    }
+
+    /// Converter of the decoder under test, [`Bt601FullRange`] by default.
+    pub fn color_converter(mut self, converter: Arc<dyn ColorConvert>) -> Self {
+        self.converter = converter;
+        self
+    }
+
+    fn compare<S, M: FrameBuffer>(
+        &mut self,
+        frame: &DecodedFrame<S, M>,
+    ) -> Result<GoldenResult, Error> {
+        let index = self.index;
+        self.index += 1;
+
+        let psnr = match self.reference.next()? {
+            Some(reference) => psnr(&reference, &to_picture(frame)?, &*self.converter),
+            None => {
+                log::warn!("no golden reference for frame {index}");
+                None
+            }
+        };
+
+        let passed = match (self.comparison, psnr) {
+            (Comparison::Exact, Some(psnr)) => psnr.is_infinite(),
+            (Comparison::Psnr(min), Some(psnr)) => psnr >= min,
+            (_, None) => false,
+        };
+
+        Ok(GoldenResult {
+            index,
+            timestamp: frame.timestamp,
+            passed,
+            psnr,
+        })
+    }
+}
+
+impl<S: Send, M: FrameBuffer> Service<DecodedFrame<S, M>> for GoldenCompare {
+    type Out = Result<GoldenResult, Error>;
+
+    fn handle(
+        &mut self,
+        frame: DecodedFrame<S, M>,
+        _cx: &flowly::Context,
+    ) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            yield self.compare(&frame);
+        }
+    }
+}
+
+impl Reference {
+    fn next(&mut self) -> Result<Option<Picture>, Error> {
+        match self {
+            Self::Y4m {
+                reader,
+                width,
+                height,
+            } => {
+                let mut line = String::new();
+
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+
+                if !line.starts_with("FRAME") {
+                    return Err(invalid("y4m frame header expected"));
+                }
+
+                let (cw, ch) = yuv::chroma_dimensions(*width, *height);
+                let mut planes = [
+                    vec![0; *width * *height],
+                    vec![0; cw * ch],
+                    vec![0; cw * ch],
+                ];
+
+                for plane in &mut planes {
+                    reader.read_exact(plane)?;
+                }
+
+                let [y, u, v] = planes;
+
+                Yuv420::from_planes(
+                    *width,
+                    *height,
+                    (*width, cw, cw),
+                    (y.into(), u.into(), v.into()),
+                )
+                .map(|picture| Some(Picture::I420(picture)))
+                .ok_or_else(|| invalid("truncated y4m frame"))
+            }
+            Self::Ppm(files) => files.next().map(|path| read_ppm(&path)).transpose(),
+        }
+    }
+}
+
+fn to_picture<S, M: FrameBuffer>(frame: &DecodedFrame<S, M>) -> Result<Picture, Error> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+
+    match &frame.chroma {
+        Some([u, v]) => Yuv420::from_planes(
+            width,
+            height,
+            (frame.strides.0, frame.strides.1, frame.strides.1),
+            (
+                bytes::Bytes::copy_from_slice(frame.data.as_slice()),
+                bytes::Bytes::copy_from_slice(u.as_slice()),
+                bytes::Bytes::copy_from_slice(v.as_slice()),
+            ),
+        )
+        .map(Picture::I420),
+        None => {
+            let (data, stride) = (frame.data.as_slice(), frame.strides.0.max(width * 3));
+            let fits = height == 0 || data.len() >= stride * (height - 1) + width * 3;
+
+            fits.then(|| {
+                let rows = data.chunks(stride.max(1)).take(height);
+
+                Picture::Rgb(
+                    width,
+                    height,
+                    rows.flat_map(|row| &row[..width * 3]).copied().collect(),
+                )
+            })
+        }
+    }
+    .ok_or(Error::InvalidFrameSize(frame.size()))
+}
+
+/// PSNR over all samples, `None` if the pictures differ in size.
+fn psnr(reference: &Picture, decoded: &Picture, converter: &dyn ColorConvert) -> Option<f64> {
+    let (squared, samples) = match (reference, decoded) {
+        (Picture::I420(a), Picture::I420(b)) => {
+            if a.dimensions() != b.dimensions() {
+                return None;
+            }
+
+            let (width, height) = a.dimensions();
+            let (cw, ch) = yuv::chroma_dimensions(width, height);
+
+            let y = plane_error(a.y(), b.y(), (a.strides().0, b.strides().0), width, height);
+            let u = plane_error(a.u(), b.u(), (a.strides().1, b.strides().1), cw, ch);
+            let v = plane_error(a.v(), b.v(), (a.strides().2, b.strides().2), cw, ch);
+
+            (y + u + v, width * height + 2 * cw * ch)
+        }
+        (reference, decoded) => {
+            let (a, b) = (reference.to_rgb(converter), decoded.to_rgb(converter));
+
+            if (a.0, a.1) != (b.0, b.1) {
+                return None;
+            }
+
+            (squared_error(&a.2, &b.2), a.2.len())
+        }
+    };
+
+    if squared == 0 {
+        return Some(f64::INFINITY);
+    }
+
+    let mse = squared as f64 / samples.max(1) as f64;
+
+    Some(10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+impl Picture {
+    /// Width, height and packed RGB rows of the picture.
+    fn to_rgb(&self, converter: &dyn ColorConvert) -> (usize, usize, Cow<'_, [u8]>) {
+        match self {
+            Self::Rgb(width, height, data) => (*width, *height, Cow::Borrowed(data)),
+            Self::I420(picture) => {
+                let (width, height) = picture.dimensions();
+                let mut out = vec![0; width * height * 3];
+
+                converter.i420_to_rgb8(
+                    &YuvPlanes {
+                        width,
+                        height,
+                        y: picture.y(),
+                        u: picture.u(),
+                        v: picture.v(),
+                        strides: picture.strides(),
+                    },
+                    &mut out,
+                );
+
+                (width, height, Cow::Owned(out))
+            }
+        }
+    }
+}
+
+fn squared_error(a: &[u8], b: &[u8]) -> u64 {
+    a.iter()
+        .zip(b)
+        .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+        .sum()
+}
+
+fn plane_error(
+    a: &[u8],
+    b: &[u8],
+    (stride_a, stride_b): (usize, usize),
+    width: usize,
+    height: usize,
+) -> u64 {
+    (0..height)
+        .map(|row| squared_error(&a[row * stride_a..][..width], &b[row * stride_b..][..width]))
+        .sum()
+}
+
+fn read_y4m_header(reader: &mut impl BufRead) -> Result<(usize, usize), Error> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut params = line.split_ascii_whitespace();
+
+    if params.next() != Some("YUV4MPEG2") {
+        return Err(invalid("not a y4m file"));
+    }
+
+    let (mut width, mut height) = (None, None);
+
+    for param in params {
+        let (key, value) = param.split_at(1);
+
+        match key {
+            "W" => width = value.parse().ok(),
+            "H" => height = value.parse().ok(),
+            // 420p10 and the like carry two bytes per sample
+            "C" if value.len() > 4
+                && value.starts_with("420p")
+                && value[4..].bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                return Err(invalid("only 8-bit y4m references are supported"));
+            }
+            "C" if !value.starts_with("420") => {
+                return Err(invalid("only 4:2:0 y4m references are supported"));
+            }
+            _ => (),
+        }
+    }
+
+    width
+        .zip(height)
+        .ok_or_else(|| invalid("y4m header lacks dimensions"))
+}
+
+fn read_ppm(path: &Path) -> Result<Picture, Error> {
+    let data = std::fs::read(path)?;
+    let mut fields = Vec::with_capacity(4);
+    let mut pos = 0;
+
+    // magic, width, height and maxval, separated by whitespace and comments
+    while fields.len() < 4 {
+        while data.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+
+        if data.get(pos) == Some(&b'#') {
+            while data.get(pos).is_some_and(|&b| b != b'\n') {
+                pos += 1;
+            }
+
+            continue;
+        }
+
+        let start = pos;
+
+        while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+
+        if start == pos {
+            return Err(invalid("truncated ppm header"));
+        }
+
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+
+    let number = |idx: usize| fields[idx].parse::<usize>().ok();
+
+    match (fields[0].as_str(), number(1), number(2), number(3)) {
+        ("P6", Some(width), Some(height), Some(255)) => {
+            // a single whitespace byte separates the header from the pixels
+            let pixels = data
+                .get(pos + 1..pos + 1 + width * height * 3)
+                .ok_or_else(|| invalid("truncated ppm image"))?;
+
+            Ok(Picture::Rgb(width, height, pixels.to_vec()))
+        }
+        _ => Err(invalid("only 8-bit binary ppm references are supported")),
+    }
+}
+
+#[inline]
+fn invalid(reason: &str) -> Error {
+    Error::InvalidReference(reason.into())
+}
@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+use flowly::{DataFrame, EncodedFrame, Frame, MemBlock, Service};
+use futures::Stream;
+
+use crate::{
+    CodecConfig, DecodedFrame, DecoderOptions, Error, FrameBuffer, Openh264Decoder,
+    codec_config::ConfigTracker, nal,
+};
+
+type Gop<S> = Vec<(Bytes, u64, S)>;
+type GopOutput<S, M> = Vec<Result<DecodedFrame<S, M>, Error>>;
+
+/// Offline decoder that splits input at IDR boundaries and decodes independent
+/// GOPs on separate decoder instances in parallel, yielding frames in input order.
+///
+/// Output lags behind input by up to `parallelism` GOPs, so this is meant for
+/// file and VOD workloads. Call [`flush`](Self::flush) after the last frame to
+/// get the tail of the stream. [`inline`](DecoderOptions::inline) instances
+/// decode on blocking threads instead of the runtime's workers.
+pub struct ParallelGopDecoder<S, M = Vec<u8>> {
+    options: DecoderOptions,
+    parallelism: usize,
+    gop: Gop<S>,
+    /// Latest parameter sets, fed ahead of every GOP.
+    config: Option<CodecConfig>,
+    tracker: ConfigTracker,
+    jobs: VecDeque<tokio::task::JoinHandle<GopOutput<S, M>>>,
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> ParallelGopDecoder<S, M> {
+    pub fn new(parallelism: usize) -> Self {
+        Self::with_options(parallelism, DecoderOptions::default())
+    }
+
+    /// `options` apply to every per-GOP decoder instance.
+    pub fn with_options(parallelism: usize, options: DecoderOptions) -> Self {
+        Self {
+            options,
+            parallelism: parallelism.max(1),
+            gop: Vec::new(),
+            config: None,
+            tracker: ConfigTracker::default(),
+            jobs: VecDeque::new(),
+        }
+    }
+
+    /// Dispatches the pending GOP and yields everything still in flight.
+    pub fn flush(&mut self) -> impl Stream<Item = Result<DecodedFrame<S, M>, Error>> + '_ {
+        async_stream::stream! {
+            self.dispatch();
+
+            while let Some(job) = self.jobs.pop_front() {
+                for res in Self::join(job).await {
+                    yield res;
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self) {
+        if self.gop.is_empty() {
+            return;
+        }
+
+        let gop = std::mem::take(&mut self.gop);
+        let options = self.options.clone();
+
+        let job = if options.inline {
+            // an inline instance decodes right in push_data, blocking its thread
+            tokio::task::spawn_blocking(move || {
+                futures::executor::block_on(Self::decode_gop(options, gop))
+            })
+        } else {
+            tokio::spawn(Self::decode_gop(options, gop))
+        };
+
+        self.jobs.push_back(job);
+    }
+
+    async fn decode_gop(options: DecoderOptions, gop: Gop<S>) -> GopOutput<S, M> {
+        let mut decoder = Openh264Decoder::<S, M>::with_options(options);
+        let mut out = Vec::new();
+
+        for (data, timestamp, source) in gop {
+            if let Err(err) = decoder.push_data(data, timestamp, source).await {
+                out.push(Err(err));
+                break;
+            }
+
+            while let Some(res) = decoder.pull_frame().transpose() {
+                out.push(res);
+            }
+        }
+
+        decoder.close();
+
+        while let Some(res) = decoder.recv_frame().await {
+            out.push(res);
+        }
+
+        out
+    }
+
+    async fn join(job: tokio::task::JoinHandle<GopOutput<S, M>>) -> GopOutput<S, M> {
+        job.await.unwrap_or_else(|err| {
+            log::error!("openh264 GOP decode task failed: {err}");
+            Vec::new()
+        })
+    }
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> Default for ParallelGopDecoder<S, M> {
+    fn default() -> Self {
+        let parallelism = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        Self::new(parallelism)
+    }
+}
+
+impl<F: EncodedFrame + 'static, M: FrameBuffer> Service<F> for ParallelGopDecoder<F::Source, M> {
+    type Out = Result<DecodedFrame<F::Source, M>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let ts = frame.timestamp();
+            let source = frame.source().clone();
+            let chunks: Vec<Bytes> = frame.into_chunks().map(|c| c.into_cpu_bytes()).collect();
+
+            // recovery points still reference frames of the previous GOP
+            if chunks.iter().any(|chunk| nal::has_idr(chunk)) {
+                self.dispatch();
+            }
+
+            for chunk in chunks {
+                if let Some(config) = self.tracker.update(&chunk) {
+                    self.config = Some(config);
+                }
+
+                // each GOP decodes on a fresh instance that has not seen the
+                // parameter sets, which may have been sent only once
+                let chunk = match &self.config {
+                    Some(config) if self.gop.is_empty() => config.prepend_to(&chunk),
+                    _ => chunk,
+                };
+
+                self.gop.push((chunk, ts, source.clone()));
+            }
+
+            while self.jobs.len() > self.parallelism
+                || self.jobs.front().is_some_and(|job| job.is_finished())
+            {
+                let Some(job) = self.jobs.pop_front() else {
+                    break;
+                };
+
+                for res in Self::join(job).await {
+                    yield res;
+                }
+            }
+        }
+    }
+}
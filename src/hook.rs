@@ -0,0 +1,49 @@
+use bytes::Bytes;
+
+use crate::DecodedFrame;
+
+/// Stream condition a decoder reports to [`DecodeHook::on_event`], next to
+/// its frames rather than in place of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderEvent {
+    /// Inter frames are dropped until a keyframe arrives, see
+    /// [`DecoderOptions::notify_waiting_for_keyframe`](crate::DecoderOptions::notify_waiting_for_keyframe).
+    WaitingForKeyframe,
+    /// The access unit with this timestamp failed to decode and the sender
+    /// should send a keyframe, see
+    /// [`DecoderOptions::request_keyframes`](crate::DecoderOptions::request_keyframes).
+    KeyframeRequested(u64),
+    /// Output for the access unit with this timestamp differs between two
+    /// decode passes, see
+    /// [`DecoderOptions::verify_determinism`](crate::DecoderOptions::verify_determinism).
+    Nondeterministic(u64),
+}
+
+/// Middleware run by the worker of an [`Openh264Decoder`](crate::Openh264Decoder),
+/// set with [`with_hook`](crate::Openh264Decoder::with_hook), for custom
+/// filtering, tagging and measurement without forking the service.
+///
+/// All methods run on the worker, between openh264 calls, so they should be quick.
+pub trait DecodeHook<S, M>: Send {
+    /// Called with every access unit as it was pushed, before anything else
+    /// looks at it. Returning `None` drops it.
+    fn before_decode(&mut self, access_unit: Bytes, timestamp: u64) -> Option<Bytes> {
+        let _ = timestamp;
+
+        Some(access_unit)
+    }
+
+    /// Called with every frame before it is accounted and handed out,
+    /// placeholders included. Decoded frames are checksummed before, as
+    /// openh264 output. Returning `false` drops it.
+    fn after_decode(&mut self, frame: &mut DecodedFrame<S, M>) -> bool {
+        let _ = frame;
+
+        true
+    }
+
+    /// Called with the events enabled in the decoder options.
+    fn on_event(&mut self, event: DecoderEvent) {
+        let _ = event;
+    }
+}
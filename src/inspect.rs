@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+
+use flowly::{DataFrame, EncodedFrame, Frame, MemBlock, Service};
+use futures::Stream;
+use serde::Serialize;
+
+use crate::{
+    bits::BitReader,
+    nal::{self, NalType},
+    sei,
+};
+
+/// Highest bit depth and QP an H.264 stream can signal.
+const MAX_BIT_DEPTH: u32 = 14;
+const MAX_QP: i32 = 51;
+
+/// Header syntax of one access unit, as produced by a [`SyntaxInspector`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessUnitInfo {
+    pub timestamp: u64,
+    pub nal_units: Vec<NalUnitInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NalUnitInfo {
+    pub nal_unit_type: u8,
+    pub nal_ref_idc: u8,
+    /// Size in bytes, without start code.
+    pub size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syntax: Option<Syntax>,
+    /// Why the header could not be parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Parsed header of a NAL unit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Syntax {
+    Sps(SpsInfo),
+    Pps(PpsInfo),
+    Slice(SliceHeaderInfo),
+    Sei { messages: Vec<SeiMessageInfo> },
+    Aud { primary_pic_type: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpsInfo {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub seq_parameter_set_id: u32,
+    pub chroma_format_idc: u32,
+    pub separate_colour_plane: bool,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub log2_max_frame_num: u32,
+    pub pic_order_cnt_type: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log2_max_pic_order_cnt_lsb: Option<u32>,
+    pub max_num_ref_frames: u32,
+    pub frame_mbs_only: bool,
+    /// Coded size in pixels.
+    pub coded_width: u32,
+    pub coded_height: u32,
+    /// Left, right, top and bottom crop in pixels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<[u32; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vui: Option<VuiInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VuiInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio_idc: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_full_range: Option<bool>,
+    /// Colour primaries, transfer characteristics and matrix coefficients.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colour_description: Option<[u8; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_units_in_tick: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_scale: Option<u32>,
+    pub nal_hrd: bool,
+    pub vcl_hrd: bool,
+    /// Absent when HRD parameters precede it, which are not parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pic_struct_present: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PpsInfo {
+    pub pic_parameter_set_id: u32,
+    pub seq_parameter_set_id: u32,
+    pub entropy_coding: EntropyCoding,
+    pub bottom_field_pic_order_in_frame_present: bool,
+    pub num_slice_groups: u32,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub weighted_pred: bool,
+    pub weighted_bipred_idc: u32,
+    pub pic_init_qp: i32,
+    pub pic_init_qs: i32,
+    pub chroma_qp_index_offset: i32,
+    pub deblocking_filter_control_present: bool,
+    pub constrained_intra_pred: bool,
+    pub redundant_pic_cnt_present: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntropyCoding {
+    Cavlc,
+    Cabac,
+}
+
+/// Leading fields of a slice header, up to the picture order count.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SliceHeaderInfo {
+    pub first_mb_in_slice: u32,
+    pub slice_type: SliceType,
+    pub pic_parameter_set_id: u32,
+    pub frame_num: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_pic: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idr_pic_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pic_order_cnt_lsb: Option<u32>,
+}
+
+/// Coding type of a picture, taken from its first slice header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PictureType {
+    Idr,
+    I,
+    P,
+    B,
+    SP,
+    SI,
+}
+
+impl PictureType {
+    /// Type of the first slice in an Annex B access unit, `None` without one.
+    pub(crate) fn of_access_unit(data: &[u8]) -> Option<Self> {
+        let nal = nal::nal_units(data).find(|nal| {
+            matches!(
+                NalType::from_header(nal[0]),
+                NalType::Slice | NalType::SlicePartitionA | NalType::IdrSlice
+            )
+        })?;
+
+        if NalType::from_header(nal[0]) == NalType::IdrSlice {
+            return Some(Self::Idr);
+        }
+
+        let rbsp = nal::to_rbsp(&nal[1..]);
+        let mut r = BitReader::new(&rbsp);
+        r.read_ue()?; // first_mb_in_slice
+
+        Some(match r.read_ue()? % 5 {
+            0 => Self::P,
+            1 => Self::B,
+            2 => Self::I,
+            3 => Self::SP,
+            _ => Self::SI,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Idr => "IDR",
+            Self::I => "I",
+            Self::P => "P",
+            Self::B => "B",
+            Self::SP => "SP",
+            Self::SI => "SI",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SliceType {
+    P,
+    B,
+    I,
+    SP,
+    SI,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SeiMessageInfo {
+    pub payload_type: u32,
+    pub payload_size: usize,
+}
+
+/// Parses SPS, PPS, slice headers, SEI and AUD of Annex B access units into
+/// a structured, serde-serializable form, e.g. to dump a stream as JSON
+/// when debugging instead of reaching for an external analyzer.
+///
+/// Slice headers need the parameter sets they refer to, so one inspector has
+/// to see a stream in order from its first SPS and PPS.
+#[derive(Debug, Default)]
+pub struct SyntaxInspector {
+    sps: HashMap<u32, SpsInfo>,
+    pps: HashMap<u32, PpsInfo>,
+}
+
+impl SyntaxInspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inspect(&mut self, data: &[u8]) -> Vec<NalUnitInfo> {
+        nal::nal_units(data)
+            .map(|unit| {
+                let (syntax, error) = match self.parse(unit) {
+                    Ok(syntax) => (syntax, None),
+                    Err(reason) => (None, Some(reason.to_string())),
+                };
+
+                NalUnitInfo {
+                    nal_unit_type: unit[0] & 0x1f,
+                    nal_ref_idc: (unit[0] >> 5) & 0x3,
+                    size: unit.len(),
+                    syntax,
+                    error,
+                }
+            })
+            .collect()
+    }
+
+    fn parse(&mut self, unit: &[u8]) -> Result<Option<Syntax>, &'static str> {
+        let rbsp = nal::to_rbsp(&unit[1..]);
+        let mut r = BitReader::new(&rbsp);
+
+        let syntax = match NalType::from_header(unit[0]) {
+            NalType::Sps => {
+                let sps = parse_sps(&mut r).ok_or("truncated or invalid sps")?;
+                self.sps.insert(sps.seq_parameter_set_id, sps.clone());
+                Syntax::Sps(sps)
+            }
+            NalType::Pps => {
+                let pps =
+                    parse_pps(&mut r).ok_or("truncated or invalid pps, or slice groups in use")?;
+                self.pps.insert(pps.pic_parameter_set_id, pps.clone());
+                Syntax::Pps(pps)
+            }
+            ty @ (NalType::Slice | NalType::IdrSlice) => {
+                Syntax::Slice(self.parse_slice(&mut r, ty == NalType::IdrSlice)?)
+            }
+            NalType::Sei => Syntax::Sei {
+                messages: sei::parse_sei(unit)
+                    .ok_or("truncated sei")?
+                    .into_iter()
+                    .map(|(payload_type, payload)| SeiMessageInfo {
+                        payload_type,
+                        payload_size: payload.len(),
+                    })
+                    .collect(),
+            },
+            NalType::Aud => Syntax::Aud {
+                primary_pic_type: r.read_bits(3).ok_or("truncated aud")? as u8,
+            },
+            _ => return Ok(None),
+        };
+
+        Ok(Some(syntax))
+    }
+
+    fn parse_slice(
+        &self,
+        r: &mut BitReader<'_>,
+        idr: bool,
+    ) -> Result<SliceHeaderInfo, &'static str> {
+        const TRUNCATED: &str = "truncated slice header";
+
+        let first_mb_in_slice = r.read_ue().ok_or(TRUNCATED)?;
+        let slice_type = match r.read_ue().ok_or(TRUNCATED)? % 5 {
+            0 => SliceType::P,
+            1 => SliceType::B,
+            2 => SliceType::I,
+            3 => SliceType::SP,
+            _ => SliceType::SI,
+        };
+        let pic_parameter_set_id = r.read_ue().ok_or(TRUNCATED)?;
+
+        let sps = self
+            .pps
+            .get(&pic_parameter_set_id)
+            .and_then(|pps| self.sps.get(&pps.seq_parameter_set_id))
+            .ok_or("slice refers to an unknown parameter set")?;
+
+        if sps.separate_colour_plane {
+            r.read_bits(2).ok_or(TRUNCATED)?; // colour_plane_id
+        }
+
+        let frame_num = r.read_bits(sps.log2_max_frame_num).ok_or(TRUNCATED)?;
+        let field_pic = if sps.frame_mbs_only {
+            None
+        } else {
+            let field = r.read_bit().ok_or(TRUNCATED)?;
+
+            if field {
+                r.read_bit().ok_or(TRUNCATED)?; // bottom_field_flag
+            }
+
+            Some(field)
+        };
+
+        let idr_pic_id = idr.then(|| r.read_ue().ok_or(TRUNCATED)).transpose()?;
+        let pic_order_cnt_lsb = sps
+            .log2_max_pic_order_cnt_lsb
+            .map(|bits| r.read_bits(bits).ok_or(TRUNCATED))
+            .transpose()?;
+
+        Ok(SliceHeaderInfo {
+            first_mb_in_slice,
+            slice_type,
+            pic_parameter_set_id,
+            frame_num,
+            field_pic,
+            idr_pic_id,
+            pic_order_cnt_lsb,
+        })
+    }
+}
+
+impl<F: EncodedFrame + 'static> Service<F> for SyntaxInspector {
+    type Out = AccessUnitInfo;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let timestamp = frame.timestamp();
+            let mut nal_units = Vec::new();
+
+            for chunk in frame.into_chunks() {
+                nal_units.extend(self.inspect(&chunk.into_cpu_bytes()));
+            }
+
+            yield AccessUnitInfo {
+                timestamp,
+                nal_units,
+            };
+        }
+    }
+}
+
+fn parse_sps(r: &mut BitReader<'_>) -> Option<SpsInfo> {
+    let profile_idc = r.read_bits(8)? as u8;
+    let constraint_flags = r.read_bits(8)? as u8;
+    let level_idc = r.read_bits(8)? as u8;
+    let seq_parameter_set_id = r.read_ue()?;
+
+    let (mut chroma_format_idc, mut bit_depth_luma, mut bit_depth_chroma) = (1, 8, 8);
+    let mut separate_colour_plane = false;
+
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.read_ue().filter(|idc| *idc <= 3)?;
+
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.read_bit()?;
+        }
+
+        bit_depth_luma = read_ranged(r, 8, MAX_BIT_DEPTH)?;
+        bit_depth_chroma = read_ranged(r, 8, MAX_BIT_DEPTH)?;
+        r.read_bit()?; // qpprime_y_zero_transform_bypass_flag
+
+        if r.read_bit()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+
+            for idx in 0..lists {
+                if r.read_bit()? {
+                    skip_scaling_list(r, if idx < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    let log2_max_frame_num = read_ranged(r, 4, 16)?;
+    let pic_order_cnt_type = r.read_ue()?;
+    let mut log2_max_pic_order_cnt_lsb = None;
+
+    match pic_order_cnt_type {
+        0 => log2_max_pic_order_cnt_lsb = Some(read_ranged(r, 4, 16)?),
+        1 => {
+            r.read_bit()?; // delta_pic_order_always_zero_flag
+            r.read_se()?; // offset_for_non_ref_pic
+            r.read_se()?; // offset_for_top_to_bottom_field
+
+            for _ in 0..r.read_ue().filter(|cycle| *cycle <= 255)? {
+                r.read_se()?;
+            }
+        }
+        _ => (),
+    }
+
+    let max_num_ref_frames = r.read_ue()?;
+    r.read_bit()?; // gaps_in_frame_num_value_allowed_flag
+
+    let coded_width = r.read_ue()?.checked_add(1)?.checked_mul(16)?;
+    let height_map_units = r.read_ue()?.checked_add(1)?;
+    let frame_mbs_only = r.read_bit()?;
+
+    if !frame_mbs_only {
+        r.read_bit()?; // mb_adaptive_frame_field_flag
+    }
+
+    r.read_bit()?; // direct_8x8_inference_flag
+
+    let coded_height = height_map_units.checked_mul(if frame_mbs_only { 16 } else { 32 })?;
+
+    // crop offsets count in chroma samples, in luma rows of both fields for interlaced
+    let (crop_x, crop_y) = match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+    let crop_y = crop_y * if frame_mbs_only { 1 } else { 2 };
+
+    let crop = if r.read_bit()? {
+        Some([
+            r.read_ue()?.checked_mul(crop_x)?,
+            r.read_ue()?.checked_mul(crop_x)?,
+            r.read_ue()?.checked_mul(crop_y)?,
+            r.read_ue()?.checked_mul(crop_y)?,
+        ])
+    } else {
+        None
+    };
+
+    let vui = if r.read_bit()? { parse_vui(r) } else { None };
+
+    Some(SpsInfo {
+        profile_idc,
+        constraint_flags,
+        level_idc,
+        seq_parameter_set_id,
+        chroma_format_idc,
+        separate_colour_plane,
+        bit_depth_luma,
+        bit_depth_chroma,
+        log2_max_frame_num,
+        pic_order_cnt_type,
+        log2_max_pic_order_cnt_lsb,
+        max_num_ref_frames,
+        frame_mbs_only,
+        coded_width,
+        coded_height,
+        crop,
+        vui,
+    })
+}
+
+fn parse_vui(r: &mut BitReader<'_>) -> Option<VuiInfo> {
+    let mut vui = VuiInfo {
+        aspect_ratio_idc: None,
+        video_full_range: None,
+        colour_description: None,
+        num_units_in_tick: None,
+        time_scale: None,
+        nal_hrd: false,
+        vcl_hrd: false,
+        pic_struct_present: None,
+    };
+
+    if r.read_bit()? {
+        let idc = r.read_bits(8)? as u8;
+
+        if idc == 255 {
+            r.read_bits(32)?; // sar_width, sar_height
+        }
+
+        vui.aspect_ratio_idc = Some(idc);
+    }
+
+    if r.read_bit()? {
+        r.read_bit()?; // overscan_appropriate_flag
+    }
+
+    if r.read_bit()? {
+        r.read_bits(3)?; // video_format
+        vui.video_full_range = Some(r.read_bit()?);
+
+        if r.read_bit()? {
+            let [_, p, t, m] = r.read_bits(24)?.to_be_bytes();
+            vui.colour_description = Some([p, t, m]);
+        }
+    }
+
+    if r.read_bit()? {
+        r.read_ue()?;
+        r.read_ue()?;
+    }
+
+    if r.read_bit()? {
+        vui.num_units_in_tick = Some(r.read_bits(32)?);
+        vui.time_scale = Some(r.read_bits(32)?);
+        r.read_bit()?; // fixed_frame_rate_flag
+    }
+
+    vui.nal_hrd = r.read_bit()?;
+
+    if !vui.nal_hrd {
+        vui.vcl_hrd = r.read_bit()?;
+
+        if !vui.vcl_hrd {
+            vui.pic_struct_present = Some(r.read_bit()?);
+        }
+    }
+
+    Some(vui)
+}
+
+fn parse_pps(r: &mut BitReader<'_>) -> Option<PpsInfo> {
+    let pic_parameter_set_id = r.read_ue()?;
+    let seq_parameter_set_id = r.read_ue()?;
+    let entropy_coding = if r.read_bit()? {
+        EntropyCoding::Cabac
+    } else {
+        EntropyCoding::Cavlc
+    };
+    let bottom_field_pic_order_in_frame_present = r.read_bit()?;
+    let num_slice_groups = r.read_ue()?.checked_add(1)?;
+
+    if num_slice_groups > 1 {
+        // slice group maps (FMO) are baseline-only and not worth describing
+        return None;
+    }
+
+    Some(PpsInfo {
+        pic_parameter_set_id,
+        seq_parameter_set_id,
+        entropy_coding,
+        bottom_field_pic_order_in_frame_present,
+        num_slice_groups,
+        num_ref_idx_l0_default_active: read_ranged(r, 1, 32)?,
+        num_ref_idx_l1_default_active: read_ranged(r, 1, 32)?,
+        weighted_pred: r.read_bit()?,
+        weighted_bipred_idc: r.read_bits(2)?,
+        // QP may go below zero by the offset of high bit depths, 6 per extra bit
+        pic_init_qp: read_qp(r, -6 * (MAX_BIT_DEPTH as i32 - 8))?,
+        pic_init_qs: read_qp(r, 0)?,
+        chroma_qp_index_offset: r.read_se().filter(|offset| (-12..=12).contains(offset))?,
+        deblocking_filter_control_present: r.read_bit()?,
+        constrained_intra_pred: r.read_bit()?,
+        redundant_pic_cnt_present: r.read_bit()?,
+    })
+}
+
+/// Reads a `ue(v)` coded as `value - offset`, `None` outside of `offset..=max`.
+fn read_ranged(r: &mut BitReader<'_>, offset: u32, max: u32) -> Option<u32> {
+    r.read_ue()?
+        .checked_add(offset)
+        .filter(|value| *value <= max)
+}
+
+/// Reads a `se(v)` coded as `qp - 26`, `None` outside of `min..=51`.
+fn read_qp(r: &mut BitReader<'_>, min: i32) -> Option<i32> {
+    r.read_se()?
+        .checked_add(26)
+        .filter(|qp| (min..=MAX_QP).contains(qp))
+}
+
+fn skip_scaling_list(r: &mut BitReader<'_>, size: usize) -> Option<()> {
+    let (mut last, mut next) = (8, 8);
+
+    for _ in 0..size {
+        if next != 0 {
+            let delta = r.read_se().filter(|delta| (-128..=127).contains(delta))?;
+            next = (last + delta + 256) % 256;
+        }
+
+        if next != 0 {
+            last = next;
+        }
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+
+    fn nal(header: u8, syntax: impl FnOnce(&mut BitWriter)) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        syntax(&mut w);
+
+        let mut out = vec![0, 0, 0, 1, header];
+        out.extend(nal::from_rbsp(&w.finish()));
+        out
+    }
+
+    /// Baseline 320x240 SPS with `frame_num_bits` bits of `frame_num`.
+    fn sps(frame_num_bits: u32) -> Vec<u8> {
+        nal(0x67, |w| {
+            w.write_bits(66, 8);
+            w.write_bits(0xc0, 8);
+            w.write_bits(30, 8);
+            w.write_ue(0); // seq_parameter_set_id
+            w.write_ue(frame_num_bits.wrapping_sub(4));
+            w.write_ue(0); // pic_order_cnt_type
+            w.write_ue(2); // log2_max_pic_order_cnt_lsb_minus4
+            w.write_ue(1); // max_num_ref_frames
+            w.write_bit(false);
+            w.write_ue(19); // pic_width_in_mbs_minus1
+            w.write_ue(14); // pic_height_in_map_units_minus1
+            w.write_bit(true); // frame_mbs_only_flag
+            w.write_bit(true); // direct_8x8_inference_flag
+            w.write_bit(false); // frame_cropping_flag
+            w.write_bit(false); // vui_parameters_present_flag
+        })
+    }
+
+    fn pps(pic_init_qp: i32) -> Vec<u8> {
+        nal(0x68, |w| {
+            w.write_ue(0); // pic_parameter_set_id
+            w.write_ue(0); // seq_parameter_set_id
+            w.write_bits(0, 2); // entropy_coding_mode_flag, bottom_field_pic_order_in_frame_present_flag
+            w.write_ue(0); // num_slice_groups_minus1
+            w.write_ue(0); // num_ref_idx_l0_default_active_minus1
+            w.write_ue(0); // num_ref_idx_l1_default_active_minus1
+            w.write_bits(0, 3); // weighted_pred_flag, weighted_bipred_idc
+            w.write_se(pic_init_qp - 26);
+            w.write_se(0); // pic_init_qs_minus26
+            w.write_se(1); // chroma_qp_index_offset
+            w.write_bits(0b100, 3);
+        })
+    }
+
+    fn idr_slice() -> Vec<u8> {
+        nal(0x65, |w| {
+            w.write_ue(0); // first_mb_in_slice
+            w.write_ue(7); // slice_type: I
+            w.write_ue(0); // pic_parameter_set_id
+            w.write_bits(0, 4); // frame_num
+            w.write_ue(3); // idr_pic_id
+            w.write_bits(6, 6); // pic_order_cnt_lsb
+        })
+    }
+
+    fn syntax(info: &NalUnitInfo) -> &Syntax {
+        info.syntax
+            .as_ref()
+            .unwrap_or_else(|| panic!("{:?}", info.error))
+    }
+
+    #[test]
+    fn inspects_parameter_sets_and_slices() {
+        let data = [sps(4), pps(24), idr_slice()].concat();
+        let units = SyntaxInspector::new().inspect(&data);
+
+        assert_eq!(units.len(), 3);
+
+        let Syntax::Sps(sps) = syntax(&units[0]) else {
+            panic!("not an sps");
+        };
+        assert_eq!((sps.coded_width, sps.coded_height), (320, 240));
+        assert_eq!(sps.log2_max_frame_num, 4);
+        assert_eq!(sps.log2_max_pic_order_cnt_lsb, Some(6));
+
+        let Syntax::Pps(pps) = syntax(&units[1]) else {
+            panic!("not a pps");
+        };
+        assert_eq!(pps.entropy_coding, EntropyCoding::Cavlc);
+        assert_eq!((pps.pic_init_qp, pps.pic_init_qs), (24, 26));
+        assert_eq!(pps.chroma_qp_index_offset, 1);
+
+        assert_eq!(
+            syntax(&units[2]),
+            &Syntax::Slice(SliceHeaderInfo {
+                first_mb_in_slice: 0,
+                slice_type: SliceType::I,
+                pic_parameter_set_id: 0,
+                frame_num: 0,
+                field_pic: None,
+                idr_pic_id: Some(3),
+                pic_order_cnt_lsb: Some(6),
+            }),
+        );
+    }
+
+    #[test]
+    fn slice_needs_parameter_sets() {
+        let units = SyntaxInspector::new().inspect(&idr_slice());
+
+        assert_eq!(units[0].syntax, None);
+        assert!(units[0].error.is_some());
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        let mut inspector = SyntaxInspector::new();
+
+        for data in [sps(17), sps(u32::MAX), pps(52), pps(-40)] {
+            let units = inspector.inspect(&data);
+
+            assert_eq!(units[0].syntax, None, "{data:02x?}");
+            assert!(units[0].error.is_some());
+        }
+
+        let high_bit_depth = nal(0x67, |w| {
+            w.write_bits(100, 8);
+            w.write_bits(0, 16);
+            w.write_ue(0); // seq_parameter_set_id
+            w.write_ue(1); // chroma_format_idc
+            w.write_ue(10); // bit_depth_luma_minus8
+            w.write_ue(0); // bit_depth_chroma_minus8
+        });
+
+        assert_eq!(inspector.inspect(&high_bit_depth)[0].syntax, None);
+    }
+
+    #[test]
+    fn picture_type_of_first_slice() {
+        let p_slice = nal(0x41, |w| {
+            w.write_ue(0); // first_mb_in_slice
+            w.write_ue(5); // slice_type: P
+        });
+
+        assert_eq!(
+            PictureType::of_access_unit(&idr_slice()),
+            Some(PictureType::Idr)
+        );
+        assert_eq!(
+            PictureType::of_access_unit(&[sps(4), p_slice].concat()),
+            Some(PictureType::P),
+        );
+        assert_eq!(PictureType::of_access_unit(&sps(4)), None);
+    }
+}
@@ -1,54 +1,182 @@
-use std::collections::BinaryHeap;
+use std::{
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::Duration,
+};
 
 use bytes::Bytes;
 use flowly::{
     DataFrame, EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, Service, VideoFrame,
     spsc,
 };
-use futures::{Stream, executor::block_on};
-use openh264::{
-    decoder::{DecoderConfig, Flush},
-    formats::YUVSource,
+use futures::Stream;
+use memory::{MemoryCounters, Reservation};
+use pool::TaskHandle;
+use worker::Worker;
+
+pub use abr::{AbrController, AbrPolicy, Feedback, LossBasedPolicy};
+#[cfg(not(target_arch = "wasm32"))]
+pub use bench::{BenchmarkClip, BenchmarkOptions, BenchmarkReport, benchmark};
+pub use buffer::{FrameAllocator, FrameBuffer, FramePool};
+pub use capture::{CaptureRecorder, CaptureReplayer, CapturedFrame};
+pub use checksum::{ChecksumAlgorithm, FrameChecksum};
+pub use codec_config::CodecConfig;
+pub use convert::{Bt601FullRange, ColorConvert, YuvPlanes};
+#[cfg(feature = "opencv")]
+pub use cv::MatFrame;
+pub use encoder::{
+    Chunking, Crop, EncodedH264Frame, EncoderControl, EncoderOptions, EncoderSettings, FillerStats,
+    FrameRateMode, Openh264Encoder, PixelFormat, SimulcastLayer,
 };
-
 pub use error::Error;
-
+#[cfg(feature = "test-support")]
+pub use golden::{Comparison, GoldenCompare, GoldenResult, GoldenSource};
+#[cfg(not(target_arch = "wasm32"))]
+pub use gop::ParallelGopDecoder;
+pub use hook::{DecodeHook, DecoderEvent};
+pub use inspect::{
+    AccessUnitInfo, EntropyCoding, NalUnitInfo, PictureType, PpsInfo, SeiMessageInfo,
+    SliceHeaderInfo, SliceType, SpsInfo, Syntax, SyntaxInspector, VuiInfo,
+};
+pub use library::{DEFAULT_LIBRARY_NAMES, Library, LibraryInfo};
+pub use ltr::LossReport;
+pub use memory::{BudgetPolicy, MemoryBudget, MemoryUsage};
+#[cfg(not(target_arch = "wasm32"))]
+pub use options::Spawner;
+pub use options::{DecoderOptions, DecoderParts, OutputFormat, ParseMode, Placeholder};
+pub use pattern::{PatternFrame, TestPattern, TestPatternOptions, TestPatternSource};
+pub use pool::WorkerPool;
+pub use qos::{DecoderQos, FeedbackBus};
+pub use reverse::ReverseDecoder;
+#[cfg(feature = "webrtc")]
+pub use rtc::{RtpDepacketizer, from_sample, to_sample};
+pub use seek::ExactSeeker;
+pub use sei::{SeiSchedule, UserDataSei};
+pub use strip::{NalFilter, SeiFilter};
+pub use tee::{TeeDecoder, TeeOutput};
+pub use timecode::TimecodeSource;
+pub use timeline::{TimelineFormat, TimelineRecorder};
+
+mod abr;
+#[cfg(not(target_arch = "wasm32"))]
+mod bench;
+mod bits;
+mod buffer;
+mod capture;
+mod checksum;
+mod clock;
+mod codec_config;
+mod convert;
+#[cfg(feature = "opencv")]
+mod cv;
+mod encoder;
 mod error;
+#[cfg(feature = "test-support")]
+mod golden;
+#[cfg(not(target_arch = "wasm32"))]
+mod gop;
+mod hook;
+mod inspect;
+mod library;
+mod ltr;
+mod memory;
+mod nal;
+mod options;
+mod pattern;
+mod pool;
+mod qos;
+mod reverse;
+#[cfg(feature = "webrtc")]
+mod rtc;
+mod seek;
+mod sei;
+mod strip;
+mod tee;
+mod timecode;
+mod timeline;
+mod worker;
+mod yuv;
 
 #[derive(Debug, Clone)]
-pub struct DecodedFrame<S> {
+pub struct DecodedFrame<S, M = Vec<u8>> {
     pub timestamp: u64,
-    pub data: Vec<u8>,
+    /// Packed RGB rows, or the Y plane of [`OutputFormat::I420`] output.
+    pub data: M,
+    /// U and V planes of [`OutputFormat::I420`] output.
+    pub chroma: Option<[M; 2]>,
+    /// Row strides in bytes of `data` and of the chroma planes.
+    pub strides: (usize, usize),
     pub width: u16,
     pub height: u16,
     pub flags: FrameFlags,
+    /// Set on placeholder frames synthesized in place of undecodable input.
+    pub synthetic: bool,
+    /// Set on the first frame of a GOP, i.e. frames decoded from an IDR or recovery point.
+    pub gop_start: bool,
+    /// Type of the picture the frame was decoded from, `None` for placeholders.
+    pub picture_type: Option<PictureType>,
+    /// Checksum of the pixel data, see [`DecoderOptions::checksum`].
+    pub checksum: Option<FrameChecksum>,
+    /// Size in bytes of the access unit the frame was decoded from.
+    pub encoded_size: usize,
+    /// Time openh264 spent in the call that returned the frame. Zero for
+    /// placeholders, frames flushed at the end of the stream and on wasm32.
+    pub decode_time: Duration,
     source: S,
+    reservation: Option<Arc<Reservation>>,
 }
 
-impl<S: FrameSource> DataFrame for DecodedFrame<S> {
+impl<S, M: FrameBuffer> DecodedFrame<S, M> {
+    #[inline]
+    pub fn format(&self) -> OutputFormat {
+        match self.chroma {
+            Some(_) => OutputFormat::I420,
+            None => OutputFormat::Rgb8,
+        }
+    }
+
+    /// Total size of all planes in bytes.
+    #[inline]
+    pub(crate) fn size(&self) -> usize {
+        self.chunks_slices().map(<[u8]>::len).sum()
+    }
+
+    #[inline]
+    fn chunks_slices(&self) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(&self.data)
+            .chain(self.chroma.iter().flatten())
+            .map(M::as_slice)
+    }
+}
+
+impl<S: FrameSource, M: FrameBuffer> DataFrame for DecodedFrame<S, M> {
     type Source = S;
-    type Chunk = Vec<u8>;
+    type Chunk = M;
 
     fn source(&self) -> &Self::Source {
         &self.source
     }
 
     fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
-        std::iter::once(self.data.as_slice())
+        std::iter::once(&self.data)
+            .chain(self.chroma.iter().flatten())
+            .map(M::chunk_ref)
     }
 
     fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
-        std::iter::once(self.data)
+        std::iter::once(self.data).chain(self.chroma.into_iter().flatten())
     }
 }
 
-impl<S: FrameSource> Frame for DecodedFrame<S> {
+impl<S: FrameSource, M: FrameBuffer> Frame for DecodedFrame<S, M> {
     fn timestamp(&self) -> u64 {
         self.timestamp
     }
 
     fn codec(&self) -> Fourcc {
-        Fourcc::PIXEL_FORMAT_RGB888
+        match self.format() {
+            OutputFormat::Rgb8 => Fourcc::PIXEL_FORMAT_RGB888,
+            OutputFormat::I420 => Fourcc::PIXEL_FORMAT_I420,
+        }
     }
 
     fn flags(&self) -> FrameFlags {
@@ -56,7 +184,7 @@ impl<S: FrameSource> Frame for DecodedFrame<S> {
     }
 }
 
-impl<S: FrameSource> VideoFrame for DecodedFrame<S> {
+impl<S: FrameSource, M: FrameBuffer> VideoFrame for DecodedFrame<S, M> {
     fn dimensions(&self) -> (u16, u16) {
         (self.width, self.height)
     }
@@ -66,121 +194,325 @@ impl<S: FrameSource> VideoFrame for DecodedFrame<S> {
     }
 }
 
-pub struct Openh264Decoder<S> {
-    sender: spsc::Sender<(Bytes, u64, S)>,
-    receiver: spsc::Receiver<Result<DecodedFrame<S>, Error>>,
-    _handler: tokio::task::JoinHandle<Result<(), Error>>,
+/// H.264 decoder service. Frames are written into `M` blocks, plain `Vec<u8>`s by default.
+///
+/// Decoding runs on a dedicated blocking thread or a [`WorkerPool`]. On
+/// wasm32, where neither is available, it runs inline in
+/// [`push_data`](Self::push_data) instead.
+pub struct Openh264Decoder<S, M = Vec<u8>> {
+    backend: Backend<S, M>,
+    counters: Arc<MemoryCounters>,
+    codec_config: Arc<Mutex<Option<CodecConfig>>>,
+    observers: Vec<Observer<S, M>>,
+}
+
+type Observer<S, M> = Box<dyn FnMut(&DecodedFrame<S, M>) + Send>;
+
+enum Backend<S, M> {
+    Threaded {
+        sender: spsc::Sender<worker::Input<S>>,
+        receiver: spsc::Receiver<worker::Output<S, M>>,
+        runner: Runner,
+    },
+    /// The worker lives in the decoder, `None` if it failed to initialize.
+    Inline {
+        worker: Option<Box<Worker<S, M>>>,
+        closed: bool,
+    },
 }
 
-impl<S: Send + Default + 'static> Openh264Decoder<S> {
+enum Runner {
+    #[cfg(not(target_arch = "wasm32"))]
+    Blocking(#[allow(dead_code)] tokio::task::JoinHandle<Result<(), Error>>),
+    /// Handed to a custom [`Spawner`], which keeps no handle.
+    #[cfg(not(target_arch = "wasm32"))]
+    Spawned,
+    Pool(TaskHandle),
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> Openh264Decoder<S, M> {
     pub fn new(_num_threads: u32) -> Self {
-        let (sender, mut rx) = spsc::channel(8);
-        let (mut tx, receiver) = spsc::channel(8);
+        Self::with_options(DecoderOptions::default())
+    }
+
+    /// Like [`new`](Self::new), but fails right away if openh264 cannot be loaded.
+    pub fn try_new() -> Result<Self, Error> {
+        Self::try_with_options(DecoderOptions::default())
+    }
+
+    /// Creates the decoder, logging initialization errors. A decoder that
+    /// failed to initialize rejects every push with
+    /// [`Error::TrySendError`]; use [`try_with_options`](Self::try_with_options)
+    /// to get the error instead.
+    pub fn with_options(options: DecoderOptions) -> Self {
+        Self::try_with_options(options).unwrap_or_else(|err| {
+            log::error!("openh264 decoder init error: {err}");
+            Self::dead()
+        })
+    }
+
+    /// Loads openh264 and sets up the decoder on the calling thread, so a
+    /// missing or broken library surfaces here rather than in the worker.
+    pub fn try_with_options(options: DecoderOptions) -> Result<Self, Error> {
+        Self::try_with_parts(options, DecoderParts::default())
+    }
 
+    /// Like [`with_options`](Self::with_options), running `hook` on every
+    /// access unit and frame.
+    pub fn with_hook(options: DecoderOptions, hook: impl DecodeHook<S, M> + 'static) -> Self {
+        Self::with_parts(options, DecoderParts::new().hook(hook))
+    }
+
+    pub fn try_with_hook(
+        options: DecoderOptions,
+        hook: impl DecodeHook<S, M> + 'static,
+    ) -> Result<Self, Error> {
+        Self::try_with_parts(options, DecoderParts::new().hook(hook))
+    }
+
+    /// Like [`with_options`](Self::with_options), with a hook or allocator.
+    pub fn with_parts(options: DecoderOptions, parts: DecoderParts<S, M>) -> Self {
+        Self::try_with_parts(options, parts).unwrap_or_else(|err| {
+            log::error!("openh264 decoder init error: {err}");
+            Self::dead()
+        })
+    }
+
+    /// A decoder that failed to initialize.
+    fn dead() -> Self {
         Self {
-            sender,
-            receiver,
-            _handler: tokio::task::spawn_blocking(move || {
-                let mut ts_heap: BinaryHeap<Entry<S>> = BinaryHeap::new();
-                let decode_config = DecoderConfig::new().flush_after_decode(Flush::NoFlush);
-
-                let mut decoder = openh264::decoder::Decoder::with_api_config(
-                    openh264::OpenH264API::from_source(),
-                    decode_config,
-                )?;
-
-                while let Some(frame) = block_on(rx.recv()) {
-                    if let Some(ts) = ts_heap.peek() {
-                        if ts.0 != frame.1 {
-                            ts_heap.push(Entry(frame.1, frame.2));
-                        }
-                    } else {
-                        ts_heap.push(Entry(frame.1, frame.2));
-                    }
-
-                    let res = decoder
-                        .decode(&frame.0)
-                        .map_err(Error::from)
-                        .map(|frame| frame.map(|frame| Self::make_frame(ts_heap.pop(), frame)));
-
-                    if let Some(res) = res.transpose() {
-                        if block_on(tx.send(res)).is_err() {
-                            break;
+            backend: Backend::Inline {
+                worker: None,
+                closed: true,
+            },
+            counters: Default::default(),
+            codec_config: Default::default(),
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn try_with_parts(
+        options: DecoderOptions,
+        parts: DecoderParts<S, M>,
+    ) -> Result<Self, Error> {
+        let counters = Arc::new(MemoryCounters::default());
+        let codec_config = Arc::new(Mutex::new(None));
+        let pool = options.worker_pool.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        let spawner = options.spawner.clone();
+
+        if cfg!(target_arch = "wasm32") || options.inline {
+            let sink = worker::Sink::Queue(Default::default());
+            let worker = Worker::new(options, sink, counters.clone(), codec_config.clone(), parts)?;
+
+            return Ok(Self {
+                backend: Backend::Inline {
+                    worker: Some(Box::new(worker)),
+                    closed: false,
+                },
+                counters,
+                codec_config,
+                observers: Vec::new(),
+            });
+        }
+
+        let (sender, rx) = spsc::channel(options.input_queue);
+        let (tx, receiver) = spsc::channel(options.decode_ahead);
+        let sink = worker::Sink::Channel(tx);
+        let worker = Worker::new(options, sink, counters.clone(), codec_config.clone(), parts)?;
+
+        let runner = match pool {
+            Some(pool) => Runner::Pool(pool.attach(worker, rx)),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => match spawner {
+                Some(Spawner::Runtime(handle)) => {
+                    Runner::Blocking(handle.spawn_blocking(move || worker.run(rx)))
+                }
+                Some(Spawner::Custom(spawn)) => {
+                    spawn(Box::new(move || {
+                        if let Err(err) = worker.run(rx) {
+                            log::error!("openh264 decoder worker failed: {err}");
                         }
-                    }
+                    }));
+
+                    Runner::Spawned
                 }
+                None => Runner::Blocking(tokio::task::spawn_blocking(move || worker.run(rx))),
+            },
+            #[cfg(target_arch = "wasm32")]
+            None => unreachable!("wasm32 decoders run inline"),
+        };
+
+        Ok(Self {
+            backend: Backend::Threaded {
+                sender,
+                receiver,
+                runner,
+            },
+            counters,
+            codec_config,
+            observers: Vec::new(),
+        })
+    }
 
-                match decoder.flush_remaining() {
-                    Ok(remaining) => {
-                        for frame in remaining {
-                            if block_on(tx.send(Ok(Self::make_frame(ts_heap.pop(), frame))))
-                                .is_err()
-                            {
-                                break;
-                            }
-                        }
-                    }
-                    Err(err) => log::error!("openh264::Decoder::flush_remaining error: {err}"),
+    #[inline]
+    pub async fn push_data(&mut self, data: Bytes, timestamp: u64, source: S) -> Result<(), Error> {
+        let size = data.len();
+
+        self.counters.input_queue.fetch_add(size, Ordering::Relaxed);
+
+        let sent = match &mut self.backend {
+            Backend::Threaded { sender, runner, .. } => {
+                let sent = sender.send((data, timestamp, source)).await.is_ok();
+
+                if sent {
+                    runner.notify();
                 }
 
-                Ok(())
-            }),
+                sent
+            }
+            Backend::Inline {
+                worker: Some(worker),
+                closed: false,
+            } => {
+                worker.receive((data, timestamp, source));
+                return Ok(());
+            }
+            Backend::Inline { .. } => false,
+        };
+
+        if !sent {
+            self.counters.input_queue.fetch_sub(size, Ordering::Relaxed);
+
+            return Err(Error::TrySendError);
         }
+
+        Ok(())
     }
 
     #[inline]
-    pub async fn push_data(&mut self, data: Bytes, timestamp: u64, source: S) -> Result<(), Error> {
-        self.sender
-            .send((data, timestamp, source))
-            .await
-            .map_err(|_| Error::TrySendError)
+    pub fn pull_frame(&mut self) -> Result<Option<DecodedFrame<S, M>>, Error> {
+        let res = match &mut self.backend {
+            Backend::Threaded { receiver, .. } => {
+                receiver.try_recv().map_err(|_| Error::TrySendError)?
+            }
+            Backend::Inline { worker, .. } => {
+                worker.as_mut().ok_or(Error::TrySendError)?.pop_output()
+            }
+        };
+
+        let Some(res) = res else {
+            return Ok(None);
+        };
+
+        self.taken();
+
+        let frame = res?;
+        self.observe(&frame);
+
+        Ok(Some(frame))
     }
 
-    #[inline]
-    pub fn pull_frame(&mut self) -> Result<Option<DecodedFrame<S>>, Error> {
-        self.receiver
-            .try_recv()
-            .map_err(|_| Error::TrySendError)?
-            .transpose()
+    /// Waits for the next decoded frame. Returns `None` once the worker is done,
+    /// which happens after [`close`](Self::close) when all pending input is decoded.
+    /// Inline decoders never wait, they return `None` whenever no frame is ready.
+    pub async fn recv_frame(&mut self) -> Option<Result<DecodedFrame<S, M>, Error>> {
+        let res = match &mut self.backend {
+            Backend::Threaded { receiver, .. } => receiver.recv().await?,
+            Backend::Inline { worker, .. } => worker.as_mut()?.pop_output()?,
+        };
+
+        self.taken();
+
+        if let Ok(frame) = &res {
+            self.observe(frame);
+        }
+
+        Some(res)
     }
 
+    /// Calls `observer` with every frame handed out from now on, whichever
+    /// way it is pulled, e.g. for frame counters or debugging.
+    pub fn on_frame(&mut self, observer: impl FnMut(&DecodedFrame<S, M>) + Send + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Frees a slot of output, resuming a pool worker parked on a full one.
+    fn taken(&self) {
+        self.counters.output_items.fetch_sub(1, Ordering::Relaxed);
+
+        if let Backend::Threaded { runner, .. } = &self.backend {
+            runner.notify();
+        }
+    }
+
+    fn observe(&mut self, frame: &DecodedFrame<S, M>) {
+        self.counters
+            .output_queue
+            .fetch_sub(frame.size(), Ordering::Relaxed);
+
+        for observer in &mut self.observers {
+            observer(frame);
+        }
+    }
+
+    /// Parameter sets last seen in the input stream.
+    pub fn codec_config(&self) -> Option<CodecConfig> {
+        self.codec_config.lock().unwrap().clone()
+    }
+
+    /// Current memory held by this decoder instance.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.counters.snapshot()
+    }
+
+    /// Signals the end of input, frames still inside the decoder are flushed.
     #[inline]
     pub fn close(&mut self) {
-        self.sender.close();
-    }
-
-    #[allow(clippy::uninit_vec)]
-    fn make_frame(
-        in_frame: Option<Entry<S>>,
-        frame: openh264::decoder::DecodedYUV<'_>,
-    ) -> DecodedFrame<S> {
-        let dims = frame.dimensions();
-        let mut data = Vec::with_capacity(dims.0 * dims.1 * 3);
-        unsafe { data.set_len(dims.0 * dims.1 * 3) };
-
-        frame.write_rgb8(&mut data);
-
-        DecodedFrame {
-            timestamp: in_frame.as_ref().map(|x| x.0).unwrap_or_default(),
-            data,
-            width: dims.0 as _,
-            height: dims.1 as _,
-            source: in_frame.map(|x| x.1).unwrap_or_default(),
-            flags: FrameFlags::VIDEO_STREAM,
+        match &mut self.backend {
+            Backend::Threaded { sender, runner, .. } => {
+                sender.close();
+                runner.notify();
+            }
+            Backend::Inline { worker, closed } => {
+                if let (Some(worker), false) = (worker, *closed) {
+                    worker.finish();
+                }
+
+                *closed = true;
+            }
+        }
+    }
+}
+
+impl Runner {
+    #[inline]
+    fn notify(&self) {
+        if let Runner::Pool(task) = self {
+            task.notify();
         }
     }
 }
 
-impl<S: Send + Default + 'static> Default for Openh264Decoder<S> {
+impl<S, M> Drop for Openh264Decoder<S, M> {
+    fn drop(&mut self) {
+        if let Backend::Threaded { sender, runner, .. } = &mut self.backend {
+            sender.close();
+            runner.notify();
+        }
+    }
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> Default for Openh264Decoder<S, M> {
     fn default() -> Self {
         Self::new(0)
     }
 }
 
-impl<F: EncodedFrame + 'static> Service<F> for Openh264Decoder<F::Source> {
-    type Out = Result<DecodedFrame<F::Source>, Error>;
+impl<F: EncodedFrame + 'static, M: FrameBuffer> Service<F> for Openh264Decoder<F::Source, M> {
+    type Out = Result<DecodedFrame<F::Source, M>, Error>;
 
-    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+    fn handle(&mut self, frame: F, cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
         async_stream::stream! {
             let ts = frame.timestamp();
             let source = frame.source().clone();
@@ -194,36 +526,12 @@ impl<F: EncodedFrame + 'static> Service<F> for Openh264Decoder<F::Source> {
             while let Some(res) = self.pull_frame().transpose() {
                 yield res;
             }
-        }
-    }
-}
 
-struct Entry<S>(u64, S);
+            let qos = self.counters.qos.lock().unwrap().take();
 
-impl<S> std::ops::Deref for Entry<S> {
-    type Target = S;
-
-    fn deref(&self) -> &Self::Target {
-        &self.1
-    }
-}
-
-impl<S> PartialEq for Entry<S> {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
-    }
-}
-
-impl<S> Eq for Entry<S> {}
-
-impl<S> PartialOrd for Entry<S> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(Self::cmp(self, other))
-    }
-}
-
-impl<S> Ord for Entry<S> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.0.cmp(&self.0)
+            if let (Some(qos), Some(bus)) = (qos, FeedbackBus::of(cx)) {
+                bus.publish_decoder_qos(qos);
+            }
+        }
     }
 }
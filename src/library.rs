@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+
+use openh264::OpenH264API;
+use openh264_sys2::API;
+
+use crate::Error;
+
+/// Where the openh264 implementation is taken from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Library {
+    /// Compiled from source into the binary (the default).
+    #[default]
+    Source,
+    /// Cisco's prebuilt shared library, loaded at runtime. Using the binaries
+    /// Cisco distributes lets them cover the H.264 patent royalties.
+    ///
+    /// With a path only that file is loaded. Otherwise the library is looked
+    /// for under [`DEFAULT_LIBRARY_NAMES`] in `$OPENH264_LIBRARY`, next to the
+    /// executable, in the platform's usual install locations and finally
+    /// through the system loader (`PATH` on Windows, `DYLD_*`/`LD_LIBRARY_PATH`
+    /// elsewhere).
+    #[cfg(feature = "dynamic")]
+    Dynamic(Option<PathBuf>),
+}
+
+/// File names the shared library goes by on the current platform, most common first.
+#[cfg(target_os = "windows")]
+pub const DEFAULT_LIBRARY_NAMES: &[&str] = &["openh264.dll", "openh264-2.4.1-win64.dll"];
+
+/// File names the shared library goes by on the current platform, most common first.
+#[cfg(target_os = "macos")]
+pub const DEFAULT_LIBRARY_NAMES: &[&str] = &["libopenh264.dylib", "libopenh264.7.dylib"];
+
+/// File names the shared library goes by on the current platform, most common first.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub const DEFAULT_LIBRARY_NAMES: &[&str] = &["libopenh264.so", "libopenh264.so.7"];
+
+#[cfg(all(feature = "dynamic", target_os = "macos"))]
+const SYSTEM_DIRS: &[&str] = &["/opt/homebrew/lib", "/usr/local/lib"];
+
+#[cfg(all(
+    feature = "dynamic",
+    not(any(target_os = "windows", target_os = "macos"))
+))]
+const SYSTEM_DIRS: &[&str] = &["/usr/local/lib", "/usr/lib", "/usr/lib64"];
+
+#[cfg(all(feature = "dynamic", target_os = "windows"))]
+const SYSTEM_DIRS: &[&str] = &[];
+
+/// Version and origin of a loaded openh264, for telling deployments apart
+/// when their behavior differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryInfo {
+    /// Major, minor and revision as reported by the library itself.
+    pub version: (u32, u32, u32),
+    /// Whether openh264 was compiled into the binary or is a prebuilt shared
+    /// library, normally Cisco's.
+    pub prebuilt: bool,
+    /// File the shared library was loaded from.
+    pub path: Option<PathBuf>,
+}
+
+impl std::fmt::Display for LibraryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (major, minor, revision) = self.version;
+
+        write!(f, "openh264 {major}.{minor}.{revision}")?;
+
+        match &self.path {
+            Some(path) => write!(f, " (prebuilt, {})", path.display()),
+            None if self.prebuilt => write!(f, " (prebuilt)"),
+            None => write!(f, " (built from source)"),
+        }
+    }
+}
+
+impl Library {
+    pub(crate) fn load(&self) -> Result<OpenH264API, Error> {
+        self.load_with_path().map(|(api, _)| api)
+    }
+
+    /// Loads the library and reports its version, e.g. to include in
+    /// support dumps.
+    pub fn info(&self) -> Result<LibraryInfo, Error> {
+        let (api, path) = self.load_with_path()?;
+
+        // SAFETY: the version query has no preconditions
+        let version = unsafe { api.WelsGetCodecVersion() };
+
+        Ok(LibraryInfo {
+            version: (version.uMajor, version.uMinor, version.uRevision),
+            prebuilt: *self != Self::Source,
+            path,
+        })
+    }
+
+    fn load_with_path(&self) -> Result<(OpenH264API, Option<PathBuf>), Error> {
+        match self {
+            Self::Source => Ok((OpenH264API::from_source(), None)),
+            #[cfg(feature = "dynamic")]
+            Self::Dynamic(Some(path)) => {
+                Ok((OpenH264API::from_blob_path(path)?, Some(path.clone())))
+            }
+            #[cfg(feature = "dynamic")]
+            Self::Dynamic(None) => load_default(),
+        }
+    }
+}
+
+#[cfg(feature = "dynamic")]
+fn load_default() -> Result<(OpenH264API, Option<PathBuf>), Error> {
+    let mut candidates = Vec::new();
+
+    if let Some(path) = std::env::var_os("OPENH264_LIBRARY") {
+        candidates.push(PathBuf::from(path));
+    }
+
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(PathBuf::from));
+
+    for dir in exe_dir
+        .into_iter()
+        .chain(SYSTEM_DIRS.iter().map(PathBuf::from))
+    {
+        candidates.extend(DEFAULT_LIBRARY_NAMES.iter().map(|name| dir.join(name)));
+    }
+
+    // bare names go through the platform's own library search
+    candidates.extend(DEFAULT_LIBRARY_NAMES.iter().map(PathBuf::from));
+
+    for path in &candidates {
+        let is_bare = path.components().count() == 1;
+
+        if !is_bare && !path.is_file() {
+            continue;
+        }
+
+        match OpenH264API::from_blob_path(path) {
+            Ok(api) => {
+                log::debug!("loaded openh264 from {}", path.display());
+                return Ok((api, Some(path.clone())));
+            }
+            Err(err) => log::debug!("failed to load openh264 from {}: {err}", path.display()),
+        }
+    }
+
+    Err(Error::LibraryNotFound(
+        candidates
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use openh264::encoder::Encoder;
+use openh264_sys2::{
+    ENCODER_LTR_RECOVERY_REQUEST, ENCODER_OPTION_LTR, LTR_RECOVERY_REQUEST, SLTRConfig,
+    SLTRRecoverRequest,
+};
+
+use crate::{Error, Syntax, SyntaxInspector};
+
+/// Frames remembered per GOP for answering loss reports.
+const MAX_FRAMES: usize = 256;
+
+/// Receiver feedback about frames that did not decode, e.g. from RTCP NACK
+/// or PLI reports, see [`EncoderControl::report_loss`](crate::EncoderControl::report_loss).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LossReport {
+    /// Timestamp of the first frame the receiver lost.
+    pub lost: u64,
+    /// Timestamp of the last frame the receiver decoded correctly, if known.
+    pub last_good: Option<u64>,
+}
+
+/// Long-term reference bookkeeping of one openh264 instance.
+pub(crate) struct Ltr {
+    pub(crate) refs: u32,
+    inspector: SyntaxInspector,
+    /// Timestamp and `idr_pic_id` of the IDR starting the current GOP.
+    idr: Option<(u64, u32)>,
+    /// Timestamps and `frame_num` of the frames encoded since.
+    frames: VecDeque<(u64, u32)>,
+}
+
+/// What a layer has to do about a [`LossReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Recovery {
+    /// An IDR was sent after the loss already.
+    None,
+    /// Reference the last good frame through LTR.
+    Ltr {
+        idr_pic_id: u32,
+        last_good: u32,
+        current: u32,
+    },
+    Idr,
+}
+
+impl Ltr {
+    pub(crate) fn new(refs: u32) -> Self {
+        Self {
+            refs,
+            inspector: SyntaxInspector::new(),
+            idr: None,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Forgets everything, e.g. when the openh264 instance is rebuilt.
+    pub(crate) fn reset(&mut self) {
+        self.idr = None;
+        self.frames.clear();
+    }
+
+    /// Records the slice header of an encoded access unit.
+    pub(crate) fn track(&mut self, timestamp: u64, data: &[u8]) {
+        let slice = self
+            .inspector
+            .inspect(data)
+            .into_iter()
+            .find_map(|unit| match unit.syntax {
+                Some(Syntax::Slice(slice)) => Some(slice),
+                _ => None,
+            });
+
+        let Some(slice) = slice else {
+            return;
+        };
+
+        if let Some(idr_pic_id) = slice.idr_pic_id {
+            self.idr = Some((timestamp, idr_pic_id));
+            self.frames.clear();
+        }
+
+        if self.frames.len() >= MAX_FRAMES {
+            self.frames.pop_front();
+        }
+
+        self.frames.push_back((timestamp, slice.frame_num));
+    }
+
+    pub(crate) fn recovery(&self, report: &LossReport) -> Recovery {
+        let Some((idr_timestamp, idr_pic_id)) = self.idr else {
+            return Recovery::Idr;
+        };
+
+        if report.lost < idr_timestamp {
+            return Recovery::None;
+        }
+
+        let last_good = report
+            .last_good
+            .filter(|last_good| *last_good < report.lost)
+            .and_then(|last_good| self.frames.iter().find(|(ts, _)| *ts == last_good));
+
+        match (last_good, self.frames.back()) {
+            (Some(&(_, last_good)), Some(&(_, current))) => Recovery::Ltr {
+                idr_pic_id,
+                last_good,
+                current,
+            },
+            _ => Recovery::Idr,
+        }
+    }
+}
+
+pub(crate) fn enable(encoder: &mut Encoder, refs: u32) -> Result<(), Error> {
+    let mut config = SLTRConfig {
+        bEnableLongTermReference: true,
+        iLTRRefNum: refs as _,
+    };
+
+    let code = unsafe {
+        encoder
+            .raw_api()
+            .set_option(ENCODER_OPTION_LTR, std::ptr::addr_of_mut!(config).cast())
+    };
+
+    if code != 0 {
+        return Err(Error::EncoderOption("long-term reference", code as _));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn request_recovery(
+    encoder: &mut Encoder,
+    idr_pic_id: u32,
+    last_good: u32,
+    current: u32,
+) -> Result<(), Error> {
+    let mut request = SLTRRecoverRequest {
+        uiFeedbackType: LTR_RECOVERY_REQUEST as _,
+        uiIDRPicId: idr_pic_id as _,
+        iLastCorrectFrameNum: last_good as _,
+        iCurrentFrameNum: current as _,
+        iLayerId: 0,
+    };
+
+    let code = unsafe {
+        encoder.raw_api().set_option(
+            ENCODER_LTR_RECOVERY_REQUEST,
+            std::ptr::addr_of_mut!(request).cast(),
+        )
+    };
+
+    if code != 0 {
+        return Err(Error::EncoderOption("ltr recovery request", code as _));
+    }
+
+    Ok(())
+}
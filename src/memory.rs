@@ -0,0 +1,166 @@
+use std::{
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{DecoderQos, clock};
+
+/// Upper bound of reference pictures openh264 keeps, plus the picture being decoded.
+const DPB_PICTURES: usize = 16 + 1;
+
+/// Snapshot of the memory held by a single decoder instance, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Encoded access units queued for the worker.
+    pub input_queue: usize,
+    /// Decoded frames buffered ahead of the consumer.
+    pub output_queue: usize,
+    /// Buffers kept by the worker between frames, e.g. the last frame for placeholders.
+    pub retained: usize,
+    /// Rough upper estimate of openh264's internal picture buffers.
+    pub decoder_estimate: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.input_queue + self.output_queue + self.retained + self.decoder_estimate
+    }
+}
+
+/// Counters shared between a decoder handle and its worker.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryCounters {
+    pub(crate) input_queue: AtomicUsize,
+    pub(crate) output_queue: AtomicUsize,
+    pub(crate) retained: AtomicUsize,
+    pub(crate) decoder_estimate: AtomicUsize,
+    /// Results sent to the decoder and not yet taken by it.
+    pub(crate) output_items: AtomicUsize,
+    /// Frames the worker dropped, for QoS reports.
+    pub(crate) dropped: AtomicU64,
+    /// QoS report of the worker not yet published.
+    pub(crate) qos: Mutex<Option<DecoderQos>>,
+}
+
+impl MemoryCounters {
+    pub(crate) fn snapshot(&self) -> MemoryUsage {
+        MemoryUsage {
+            input_queue: self.input_queue.load(Ordering::Relaxed),
+            output_queue: self.output_queue.load(Ordering::Relaxed),
+            retained: self.retained.load(Ordering::Relaxed),
+            decoder_estimate: self.decoder_estimate.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn set_dimensions(&self, width: u16, height: u16) {
+        let picture = width as usize * height as usize * 3 / 2;
+
+        self.decoder_estimate
+            .store(picture * DPB_PICTURES, Ordering::Relaxed);
+    }
+}
+
+/// What a decoder does with a frame that does not fit into its [`MemoryBudget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Drop the frame right away.
+    Drop,
+    /// Stall the decoder until other frames are released, dropping the frame
+    /// if the budget is still exhausted after the given time. Same as
+    /// [`Drop`](Self::Drop) for inline decoders and decoders on a
+    /// [`WorkerPool`](crate::WorkerPool), which must not block their thread.
+    Throttle(Duration),
+}
+
+/// Memory cap on decoded frames shared by any number of decoder instances.
+///
+/// Every decoded frame holds its share of the budget until the frame (and all
+/// its clones) is dropped.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    inner: Arc<BudgetInner>,
+}
+
+#[derive(Debug)]
+struct BudgetInner {
+    limit: usize,
+    used: Mutex<usize>,
+    released: Condvar,
+    dropped: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(BudgetInner {
+                limit,
+                used: Mutex::new(0),
+                released: Condvar::new(),
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Bytes currently held by live decoded frames.
+    pub fn used(&self) -> usize {
+        *self.inner.used.lock().unwrap()
+    }
+
+    /// Number of frames dropped so far because the budget was exhausted.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reserve(&self, size: usize, policy: BudgetPolicy) -> Option<Reservation> {
+        let inner = &self.inner;
+        let mut used = inner.used.lock().unwrap();
+
+        // a frame bigger than the whole budget still gets through when nothing else is held
+        let fits = |used: usize| used == 0 || used + size <= inner.limit;
+
+        if let (BudgetPolicy::Throttle(timeout), Some(started)) = (policy, clock::now()) {
+            while !fits(*used) {
+                let Some(left) = timeout.checked_sub(started.elapsed()) else {
+                    break;
+                };
+
+                used = inner.released.wait_timeout(used, left).unwrap().0;
+            }
+        }
+
+        if !fits(*used) {
+            inner.dropped.fetch_add(1, Ordering::Relaxed);
+
+            return None;
+        }
+
+        *used += size;
+
+        Some(Reservation {
+            budget: inner.clone(),
+            size,
+        })
+    }
+}
+
+/// Share of a [`MemoryBudget`] held by a decoded frame.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    budget: Arc<BudgetInner>,
+    size: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.size;
+        self.budget.released.notify_all();
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::bits::BitReader;
+
+/// NAL unit type, as carried in the low five bits of the NAL header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NalType {
+    Slice,
+    SlicePartitionA,
+    SlicePartitionB,
+    SlicePartitionC,
+    IdrSlice,
+    Sei,
+    Sps,
+    Pps,
+    Aud,
+    EndOfSequence,
+    EndOfStream,
+    Filler,
+    /// SVC prefix of a base layer slice.
+    Prefix,
+    SubsetSps,
+    /// SVC enhancement layer slice.
+    SliceExtension,
+    Other(u8),
+}
+
+impl NalType {
+    pub(crate) fn from_header(header: u8) -> Self {
+        match header & 0x1f {
+            1 => Self::Slice,
+            2 => Self::SlicePartitionA,
+            3 => Self::SlicePartitionB,
+            4 => Self::SlicePartitionC,
+            5 => Self::IdrSlice,
+            6 => Self::Sei,
+            7 => Self::Sps,
+            8 => Self::Pps,
+            9 => Self::Aud,
+            10 => Self::EndOfSequence,
+            11 => Self::EndOfStream,
+            12 => Self::Filler,
+            14 => Self::Prefix,
+            15 => Self::SubsetSps,
+            20 => Self::SliceExtension,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Coarse classification of an access unit for keyframe gating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccessUnitKind {
+    /// Contains an IDR slice or a recovery point SEI.
+    Keyframe,
+    /// Contains only non-IDR slices.
+    Inter,
+    /// Contains no slices at all (parameter sets, SEI, AUD, ...).
+    NonVcl,
+}
+
+pub(crate) fn classify(data: &[u8]) -> AccessUnitKind {
+    let mut kind = AccessUnitKind::NonVcl;
+
+    for nal in nal_units(data) {
+        match NalType::from_header(nal[0]) {
+            NalType::IdrSlice => return AccessUnitKind::Keyframe,
+            NalType::Sei if sei_has_recovery_point(nal) => return AccessUnitKind::Keyframe,
+            NalType::Slice
+            | NalType::SlicePartitionA
+            | NalType::SlicePartitionB
+            | NalType::SlicePartitionC => kind = AccessUnitKind::Inter,
+            _ => (),
+        }
+    }
+
+    kind
+}
+
+/// Whether `data` carries an IDR slice, the only point every later frame
+/// decodes from without reference to earlier ones.
+pub(crate) fn has_idr(data: &[u8]) -> bool {
+    nal_units(data).any(|nal| NalType::from_header(nal[0]) == NalType::IdrSlice)
+}
+
+/// Human readable NAL composition of an access unit, e.g.
+/// `Sps 12B ref 3, Pps 4B ref 3, IdrSlice 5032B ref 3`.
+pub(crate) fn describe(data: &[u8]) -> String {
+    let units: Vec<_> = nal_units(data)
+        .map(|nal| {
+            format!(
+                "{:?} {}B ref {}",
+                NalType::from_header(nal[0]),
+                nal.len(),
+                (nal[0] >> 5) & 0x3
+            )
+        })
+        .collect();
+
+    if units.is_empty() {
+        return "no Annex B start code found".into();
+    }
+
+    units.join(", ")
+}
+
+/// Checks the Annex B structure of an access unit, returning what is wrong
+/// with it for strict parsing.
+pub(crate) fn validate(data: &[u8]) -> Result<(), String> {
+    let Some(first) = find_start_code(data) else {
+        return Err("no Annex B start code found".into());
+    };
+
+    if data[..first].iter().any(|&b| b != 0) {
+        return Err(format!(
+            "{first} bytes of garbage before the first start code"
+        ));
+    }
+
+    for nal in nal_units(data) {
+        let ty = NalType::from_header(nal[0]);
+
+        if let Some(reason) = malformed(nal) {
+            return Err(format!("{ty:?} NAL unit: {reason}"));
+        }
+
+        if ty == NalType::Sei && nal.len() > 1 && crate::sei::parse_sei(nal).is_none() {
+            return Err("truncated SEI message".into());
+        }
+
+        if matches!(ty, NalType::Sps | NalType::Pps | NalType::IdrSlice) && nal[0] & 0x60 == 0 {
+            return Err(format!("{ty:?} NAL unit with nal_ref_idc 0"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Drops what does not belong to any well-formed NAL unit, for permissive
+/// parsing: bytes before the first start code and broken NAL units.
+/// `None` if nothing had to be dropped, or if `data` has no start code at all
+/// and is left to openh264 as is.
+pub(crate) fn resync(data: &[u8]) -> Option<Bytes> {
+    let start = find_start_code(data)?;
+    let clean_start = data[..start].iter().all(|&b| b == 0);
+
+    if clean_start && nal_units(data).all(|nal| malformed(nal).is_none()) {
+        return None;
+    }
+
+    let mut out = BytesMut::with_capacity(data.len());
+
+    for nal in nal_units(data) {
+        match malformed(nal) {
+            Some(reason) => log::debug!(
+                "dropping {:?} NAL unit: {reason}",
+                NalType::from_header(nal[0])
+            ),
+            None => {
+                out.put_slice(&[0, 0, 0, 1]);
+                out.put_slice(nal);
+            }
+        }
+    }
+
+    Some(out.freeze())
+}
+
+fn malformed(nal: &[u8]) -> Option<&'static str> {
+    let ty = NalType::from_header(nal[0]);
+
+    if nal[0] & 0x80 != 0 {
+        return Some("forbidden_zero_bit set");
+    }
+
+    if nal.len() == 1
+        && matches!(
+            ty,
+            NalType::Slice | NalType::IdrSlice | NalType::Sps | NalType::Pps | NalType::Sei
+        )
+    {
+        return Some("empty payload");
+    }
+
+    // a zero run the encoder should have broken up with an emulation prevention byte
+    if nal.windows(3).any(|w| w[0] == 0 && w[1] == 0 && w[2] <= 2) {
+        return Some("missing emulation prevention");
+    }
+
+    None
+}
+
+/// Iterates over the NAL units of an Annex B byte stream, without start codes.
+///
+/// Data that does not start with a start code yields nothing.
+pub(crate) fn nal_units(data: &[u8]) -> NalUnits<'_> {
+    let pos = find_start_code(data).map(|at| at + 3).unwrap_or(data.len());
+
+    NalUnits { data, pos }
+}
+
+pub(crate) struct NalUnits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.data.len() {
+            let rest = &self.data[self.pos..];
+            let (mut nal, advance) = match find_start_code(rest) {
+                Some(at) => (&rest[..at], at + 3),
+                None => (rest, rest.len()),
+            };
+
+            self.pos += advance;
+
+            // trailing zeroes belong to a 4-byte start code or trailing_zero_8bits
+            while let [head @ .., 0] = nal {
+                nal = head;
+            }
+
+            if !nal.is_empty() {
+                return Some(nal);
+            }
+        }
+
+        None
+    }
+}
+
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|w| w == [0, 0, 1])
+}
+
+/// Strips emulation prevention bytes from a NAL unit payload.
+pub(crate) fn to_rbsp(payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len());
+    let mut zeroes = 0;
+
+    for &byte in payload {
+        if zeroes >= 2 && byte == 3 {
+            zeroes = 0;
+            continue;
+        }
+
+        zeroes = if byte == 0 { zeroes + 1 } else { 0 };
+        out.push(byte);
+    }
+
+    out
+}
+
+/// Inserts emulation prevention bytes, the inverse of [`to_rbsp`].
+pub(crate) fn from_rbsp(rbsp: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rbsp.len() + rbsp.len() / 64);
+    let mut zeroes = 0;
+
+    for &byte in rbsp {
+        if zeroes >= 2 && byte <= 3 {
+            out.push(3);
+            zeroes = 0;
+        }
+
+        zeroes = if byte == 0 { zeroes + 1 } else { 0 };
+        out.push(byte);
+    }
+
+    out
+}
+
+/// Inserts NAL units, given without start codes, in front of the first slice
+/// of an Annex B access unit, or at its end if it has none.
+pub(crate) fn insert_before_vcl(data: &[u8], units: &[Vec<u8>]) -> Bytes {
+    let at = nal_units(data)
+        .find(|nal| {
+            matches!(
+                NalType::from_header(nal[0]),
+                NalType::Slice
+                    | NalType::SlicePartitionA
+                    | NalType::SlicePartitionB
+                    | NalType::SlicePartitionC
+                    | NalType::IdrSlice
+            )
+        })
+        .map(|nal| start_code_offset(data, nal))
+        .unwrap_or(data.len());
+
+    let mut out =
+        BytesMut::with_capacity(data.len() + units.iter().map(|u| u.len() + 4).sum::<usize>());
+
+    out.put_slice(&data[..at]);
+
+    for unit in units {
+        out.put_slice(&[0, 0, 0, 1]);
+        out.put_slice(unit);
+    }
+
+    out.put_slice(&data[at..]);
+    out.freeze()
+}
+
+/// Puts a NAL unit, given without start code, in front of an Annex B access unit.
+pub(crate) fn prepend(data: &[u8], unit: &[u8]) -> Bytes {
+    let mut out = BytesMut::with_capacity(data.len() + unit.len() + 4);
+
+    out.put_slice(&[0, 0, 0, 1]);
+    out.put_slice(unit);
+    out.put_slice(data);
+    out.freeze()
+}
+
+/// Puts a NAL unit, given without start code, at the end of an Annex B access unit.
+pub(crate) fn append(data: &[u8], unit: &[u8]) -> Bytes {
+    let mut out = BytesMut::with_capacity(data.len() + unit.len() + 4);
+
+    out.put_slice(data);
+    out.put_slice(&[0, 0, 0, 1]);
+    out.put_slice(unit);
+    out.freeze()
+}
+
+/// Rebuilds an Annex B access unit with 4-byte start codes, replacing the
+/// NAL units `f` returns a substitute for.
+pub(crate) fn map_units(data: &[u8], mut f: impl FnMut(&[u8]) -> Option<Vec<u8>>) -> Bytes {
+    let mut out = BytesMut::with_capacity(data.len() + 16);
+
+    for nal in nal_units(data) {
+        out.put_slice(&[0, 0, 0, 1]);
+
+        match f(nal) {
+            Some(replaced) => out.put_slice(&replaced),
+            None => out.put_slice(nal),
+        }
+    }
+
+    out.freeze()
+}
+
+/// Offset of the start code in front of `nal`, a slice of `data`.
+fn start_code_offset(data: &[u8], nal: &[u8]) -> usize {
+    let offset = nal.as_ptr() as usize - data.as_ptr() as usize - 3;
+
+    if offset > 0 && data[offset - 1] == 0 {
+        offset - 1
+    } else {
+        offset
+    }
+}
+
+fn sei_has_recovery_point(nal: &[u8]) -> bool {
+    const RECOVERY_POINT: u32 = 6;
+
+    let rbsp = to_rbsp(&nal[1..]);
+    let mut pos = 0;
+
+    while pos < rbsp.len() && rbsp[pos] != 0x80 {
+        let Some(payload_type) = read_sei_value(&rbsp, &mut pos) else {
+            break;
+        };
+
+        let Some(payload_size) = read_sei_value(&rbsp, &mut pos) else {
+            break;
+        };
+
+        if payload_type == RECOVERY_POINT {
+            return true;
+        }
+
+        pos += payload_size as usize;
+    }
+
+    false
+}
+
+fn read_sei_value(rbsp: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value = 0;
+
+    loop {
+        let byte = *rbsp.get(*pos)?;
+        *pos += 1;
+        value += byte as u32;
+
+        if byte != 0xff {
+            return Some(value);
+        }
+    }
+}
+
+/// SPS and PPS last seen per id, to drop bit-identical repeats in front of
+/// every IDR before they reach the decoder.
+#[derive(Debug, Default)]
+pub(crate) struct ParameterSets {
+    sps: HashMap<u32, Vec<u8>>,
+    pps: HashMap<u32, Vec<u8>>,
+}
+
+impl ParameterSets {
+    /// Removes parameter sets identical to the last ones seen with the same
+    /// id, `None` if nothing was removed.
+    pub(crate) fn dedup(&mut self, data: &[u8]) -> Option<Bytes> {
+        let mut changed = false;
+        let mut out = BytesMut::with_capacity(data.len());
+
+        for unit in nal_units(data) {
+            let repeat = match NalType::from_header(unit[0]) {
+                NalType::Sps => parameter_set_id(unit, 3).is_some_and(|id| {
+                    let repeat = self.sps.get(&id).is_some_and(|last| last[..] == *unit);
+
+                    if !repeat {
+                        // PPS are parsed against their SPS, a new SPS needs them resent
+                        self.pps.clear();
+                        self.sps.insert(id, unit.to_vec());
+                    }
+
+                    repeat
+                }),
+                NalType::Pps => parameter_set_id(unit, 0).is_some_and(|id| {
+                    let repeat = self.pps.get(&id).is_some_and(|last| last[..] == *unit);
+
+                    if !repeat {
+                        self.pps.insert(id, unit.to_vec());
+                    }
+
+                    repeat
+                }),
+                _ => false,
+            };
+
+            if repeat {
+                changed = true;
+            } else {
+                out.put_slice(&[0, 0, 0, 1]);
+                out.put_slice(unit);
+            }
+        }
+
+        changed.then(|| out.freeze())
+    }
+
+    /// Forgets everything, so the next parameter sets reach the decoder again.
+    pub(crate) fn clear(&mut self) {
+        self.sps.clear();
+        self.pps.clear();
+    }
+}
+
+/// The `ue(v)` id `skip` bytes into the payload of a parameter set.
+pub(crate) fn parameter_set_id(unit: &[u8], skip: usize) -> Option<u32> {
+    let rbsp = to_rbsp(unit.get(1 + skip..)?);
+
+    BitReader::new(&rbsp).read_ue()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1e, 0x80];
+    const PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84];
+
+    fn annex_b(units: &[&[u8]]) -> Vec<u8> {
+        units
+            .iter()
+            .flat_map(|unit| [&[0, 0, 0, 1][..], unit].concat())
+            .collect()
+    }
+
+    #[test]
+    fn validate_accepts_well_formed_access_unit() {
+        assert_eq!(validate(&annex_b(&[SPS, PPS, IDR])), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_broken_structure() {
+        assert!(validate(&IDR[..]).is_err());
+        assert!(validate(&[&[0xff][..], &annex_b(&[IDR])].concat()).is_err());
+        assert!(validate(&annex_b(&[&[0xe5, 0x88]])).is_err());
+        assert!(validate(&annex_b(&[&[0x65]])).is_err());
+        assert!(validate(&annex_b(&[&[0x65, 0x00, 0x00, 0x02]])).is_err());
+
+        // parameter sets and IDR slices must be reference pictures
+        assert!(validate(&annex_b(&[&[0x07, 0x42, 0x00, 0x1e, 0x80]])).is_err());
+    }
+
+    #[test]
+    fn resync_keeps_clean_data() {
+        assert_eq!(resync(&annex_b(&[SPS, PPS, IDR])), None);
+        assert_eq!(resync(IDR), None);
+    }
+
+    #[test]
+    fn resync_drops_garbage_and_broken_units() {
+        let data = [&[0xff, 0x00, 0x00, 0x01, 0xe5, 0x88][..], &annex_b(&[IDR])].concat();
+
+        assert_eq!(resync(&data).as_deref(), Some(&annex_b(&[IDR])[..]));
+    }
+
+    #[test]
+    fn rbsp_roundtrip() {
+        let rbsp = [0, 0, 0, 0, 0, 1, 0, 0, 2, 5];
+        let escaped = from_rbsp(&rbsp);
+
+        assert_eq!(escaped, [0, 0, 3, 0, 0, 3, 0, 1, 0, 0, 3, 2, 5]);
+        assert_eq!(to_rbsp(&escaped), rbsp);
+        assert_eq!(to_rbsp(&[1, 2, 3]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn dedup_drops_repeated_parameter_sets() {
+        let mut sets = ParameterSets::default();
+        let au = annex_b(&[SPS, PPS, IDR]);
+
+        assert_eq!(sets.dedup(&au), None);
+        assert_eq!(sets.dedup(&au).as_deref(), Some(&annex_b(&[IDR])[..]));
+
+        // a changed SPS with the same id brings its PPS back, even if unchanged
+        let sps = [0x67, 0x42, 0x00, 0x1f, 0x80];
+        assert_eq!(sets.dedup(&annex_b(&[&sps, PPS, IDR])), None);
+        assert_eq!(sets.dedup(&annex_b(&[SPS, PPS, IDR])), None);
+
+        sets.clear();
+        assert_eq!(sets.dedup(&au), None);
+    }
+
+    #[test]
+    fn dedup_tracks_ids_separately() {
+        let mut sets = ParameterSets::default();
+        let other = [0x68, 0x5e, 0x38, 0x80];
+
+        assert_eq!(sets.dedup(&annex_b(&[SPS, PPS, IDR])), None);
+        assert_eq!(
+            sets.dedup(&annex_b(&[&other, PPS, IDR])).as_deref(),
+            Some(&annex_b(&[&other, IDR])[..]),
+        );
+    }
+}
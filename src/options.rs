@@ -0,0 +1,356 @@
+use std::{ops::RangeInclusive, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{
+    Bt601FullRange, BudgetPolicy, ChecksumAlgorithm, ColorConvert, DecodeHook, FrameAllocator,
+    FrameBuffer, Library, MemoryBudget, NalFilter, WorkerPool,
+};
+
+/// Configuration of an [`Openh264Decoder`](crate::Openh264Decoder).
+#[derive(Debug, Clone)]
+pub struct DecoderOptions {
+    pub(crate) library: Library,
+    pub(crate) keyframe_gating: bool,
+    pub(crate) notify_waiting_for_keyframe: bool,
+    pub(crate) request_keyframes: bool,
+    pub(crate) placeholder: Option<Placeholder>,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) color_converter: Arc<dyn ColorConvert>,
+    pub(crate) checksum: Option<ChecksumAlgorithm>,
+    pub(crate) verify_determinism: bool,
+    pub(crate) log_nal_units: bool,
+    pub(crate) error_capture: Option<(usize, PathBuf)>,
+    pub(crate) nal_filter: NalFilter,
+    pub(crate) parse_mode: ParseMode,
+    pub(crate) expected_resolution: Option<(u16, u16)>,
+    pub(crate) dedup_parameter_sets: bool,
+    pub(crate) time_range: Option<RangeInclusive<u64>>,
+    pub(crate) inline: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) spawner: Option<Spawner>,
+    pub(crate) input_queue: usize,
+    pub(crate) decode_ahead: usize,
+    pub(crate) memory_budget: Option<(MemoryBudget, BudgetPolicy)>,
+    pub(crate) worker_pool: Option<WorkerPool>,
+    pub(crate) qos: Option<Duration>,
+}
+
+/// Pixel layout of decoded frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Packed 8-bit RGB in a single chunk.
+    #[default]
+    Rgb8,
+    /// 8-bit planar YUV 4:2:0 as three chunks, Y, U and V, copied with
+    /// the decoder's row strides.
+    I420,
+}
+
+/// How the decoder treats input that violates the Annex B syntax.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Reject every malformed access unit with
+    /// [`Error::Malformed`](crate::Error::Malformed) without decoding it,
+    /// for ingest validation.
+    Strict,
+    /// Drop garbage and broken NAL units and decode the rest. With keyframe
+    /// gating, a decoding error also drops frames until the next keyframe
+    /// instead of decoding against corrupted references. For flaky network
+    /// sources.
+    #[default]
+    Permissive,
+}
+
+/// Extensions of an [`Openh264Decoder`](crate::Openh264Decoder) that depend
+/// on its source and buffer types, set up next to its [`DecoderOptions`]
+/// with [`with_parts`](crate::Openh264Decoder::with_parts).
+pub struct DecoderParts<S, M> {
+    pub(crate) hook: Option<Box<dyn DecodeHook<S, M>>>,
+    pub(crate) allocator: Option<Box<dyn FrameAllocator<M>>>,
+}
+
+impl<S, M: FrameBuffer> DecoderParts<S, M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` on every access unit and frame.
+    pub fn hook(mut self, hook: impl DecodeHook<S, M> + 'static) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Allocate frame memory with `allocator` instead of [`FrameBuffer::alloc`].
+    pub fn allocator(mut self, allocator: impl FrameAllocator<M> + 'static) -> Self {
+        self.allocator = Some(Box::new(allocator));
+        self
+    }
+}
+
+impl<S, M> Default for DecoderParts<S, M> {
+    fn default() -> Self {
+        Self {
+            hook: None,
+            allocator: None,
+        }
+    }
+}
+
+/// Where the blocking worker of a decoder is started, see [`DecoderOptions::spawner`].
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub enum Spawner {
+    /// `spawn_blocking` on the given runtime.
+    Runtime(tokio::runtime::Handle),
+    /// Any executor able to run a blocking closure, e.g. a dedicated thread.
+    Custom(Arc<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Spawner {
+    pub fn custom(spawn: impl Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(spawn))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl std::fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Runtime(handle) => f.debug_tuple("Runtime").field(handle).finish(),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// What to emit in place of a frame that failed to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// Repeat the last successfully decoded frame.
+    RepeatLast,
+    /// Fill the frame with a single RGB color.
+    SolidColor([u8; 3]),
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self {
+            library: Library::Source,
+            keyframe_gating: true,
+            notify_waiting_for_keyframe: false,
+            request_keyframes: false,
+            placeholder: None,
+            output_format: OutputFormat::Rgb8,
+            color_converter: Arc::new(Bt601FullRange),
+            checksum: None,
+            verify_determinism: false,
+            log_nal_units: false,
+            error_capture: None,
+            nal_filter: NalFilter::default(),
+            parse_mode: ParseMode::Permissive,
+            expected_resolution: None,
+            dedup_parameter_sets: true,
+            time_range: None,
+            inline: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            spawner: None,
+            input_queue: 8,
+            decode_ahead: 8,
+            memory_budget: None,
+            worker_pool: None,
+            qos: None,
+        }
+    }
+}
+
+impl DecoderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where to take openh264 from. Defaults to [`Library::Source`].
+    pub fn library(mut self, library: Library) -> Self {
+        self.library = library;
+        self
+    }
+
+    /// Drop inter frames until the first IDR or recovery point is seen,
+    /// so joining a stream mid-GOP does not produce corrupted output. Enabled by default.
+    pub fn keyframe_gating(mut self, enabled: bool) -> Self {
+        self.keyframe_gating = enabled;
+        self
+    }
+
+    /// Report [`DecoderEvent::WaitingForKeyframe`](crate::DecoderEvent::WaitingForKeyframe)
+    /// to the [`DecodeHook`](crate::DecodeHook) once when the first inter frame
+    /// is dropped by keyframe gating. Disabled by default.
+    pub fn notify_waiting_for_keyframe(mut self, enabled: bool) -> Self {
+        self.notify_waiting_for_keyframe = enabled;
+        self
+    }
+
+    /// Report [`DecoderEvent::KeyframeRequested`](crate::DecoderEvent::KeyframeRequested)
+    /// to the [`DecodeHook`](crate::DecodeHook) when an access unit fails to
+    /// decode, once until the next keyframe decodes, for forwarding to the sender, e.g. as a PLI or with
+    /// [`EncoderControl::request_keyframe`](crate::EncoderControl::request_keyframe).
+    /// Disabled by default.
+    pub fn request_keyframes(mut self, enabled: bool) -> Self {
+        self.request_keyframes = enabled;
+        self
+    }
+
+    /// Emit a placeholder frame, flagged as [`synthetic`](crate::DecodedFrame::synthetic),
+    /// for every access unit that fails to decode, so fixed-rate consumers keep a
+    /// continuous timeline. Placeholders take the dimensions of the last decoded frame,
+    /// so nothing is emitted before the first one. Disabled by default.
+    pub fn placeholder(mut self, placeholder: Option<Placeholder>) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    /// Pixel layout of decoded frames. Defaults to [`OutputFormat::Rgb8`].
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Convert [`OutputFormat::Rgb8`] output with `converter`. Defaults to
+    /// [`Bt601FullRange`](crate::Bt601FullRange).
+    pub fn color_converter(mut self, converter: impl ColorConvert + 'static) -> Self {
+        self.color_converter = Arc::new(converter);
+        self
+    }
+
+    /// Attach a checksum of every decoded frame, taken over its I420 planes
+    /// whatever the output format, e.g. to diff a pipeline against a reference
+    /// decoder's `framemd5 -pix_fmt yuv420p` output. Disabled by default.
+    pub fn checksum(mut self, algorithm: Option<ChecksumAlgorithm>) -> Self {
+        self.checksum = algorithm;
+        self
+    }
+
+    /// Decode every access unit a second time on a separate openh264 instance
+    /// and report [`DecoderEvent::Nondeterministic`](crate::DecoderEvent::Nondeterministic)
+    /// to the [`DecodeHook`](crate::DecodeHook) whenever the two passes differ, e.g. for archival and forensic use where
+    /// output has to be reproducible. Doubles the decoding cost. Disabled by default.
+    pub fn verify_determinism(mut self, enabled: bool) -> Self {
+        self.verify_determinism = enabled;
+        self
+    }
+
+    /// Log the NAL unit types, sizes and reference flags of every submitted
+    /// access unit at info level, to diagnose streams that decode to nothing.
+    /// Disabled by default.
+    pub fn log_nal_units(mut self, enabled: bool) -> Self {
+        self.log_nal_units = enabled;
+        self
+    }
+
+    /// Keep the last `depth` access units and, when one fails to decode, write
+    /// them to an Annex B file in `dir`, preceded by the last seen parameter
+    /// sets, for offline reproduction. Each dump starts a new capture.
+    pub fn capture_errors(mut self, depth: usize, dir: impl Into<PathBuf>) -> Self {
+        self.error_capture = Some((depth.max(1), dir.into()));
+        self
+    }
+
+    /// Remove access unit delimiters, filler data or SEI messages from every
+    /// access unit before it is decoded, to cut overhead and to work around
+    /// decoder quirks with unusual NAL mixes. Keyframe gating still sees the
+    /// unfiltered input. Keeps everything by default.
+    pub fn nal_filter(mut self, filter: NalFilter) -> Self {
+        self.nal_filter = filter;
+        self
+    }
+
+    /// Strict or permissive handling of malformed input. Defaults to
+    /// [`ParseMode::Permissive`].
+    pub fn parse_mode(mut self, mode: ParseMode) -> Self {
+        self.parse_mode = mode;
+        self
+    }
+
+    /// Resolution of the stream, if known up front. Memory accounting and
+    /// the placeholder buffers are then sized at construction instead of on
+    /// the first frame.
+    pub fn expected_resolution(mut self, width: u16, height: u16) -> Self {
+        self.expected_resolution = Some((width, height));
+        self
+    }
+
+    /// Skip SPS and PPS that are bit-identical to the last ones with the same
+    /// id instead of handing them to the decoder again, as streams repeating
+    /// them before every IDR would. Changed parameter sets always get
+    /// through, and after a decoding error the next ones do, too. Enabled by default.
+    pub fn dedup_parameter_sets(mut self, enabled: bool) -> Self {
+        self.dedup_parameter_sets = enabled;
+        self
+    }
+
+    /// Number of access units that may be queued for the worker before
+    /// pushing waits. Defaults to 8.
+    pub fn input_queue(mut self, depth: usize) -> Self {
+        self.input_queue = depth.max(1);
+        self
+    }
+
+    /// Number of decoded frames the worker may buffer ahead of the consumer.
+    ///
+    /// A deep buffer (e.g. 16-32) smooths over consumer stalls in VOD pipelines,
+    /// a shallow one (1-2) keeps latency and memory low for live streams. Defaults to 8.
+    pub fn decode_ahead(mut self, depth: usize) -> Self {
+        self.decode_ahead = depth.max(1);
+        self
+    }
+
+    /// Account decoded frames against a budget shared with other decoders,
+    /// applying `policy` to frames that do not fit.
+    pub fn memory_budget(mut self, budget: MemoryBudget, policy: BudgetPolicy) -> Self {
+        self.memory_budget = Some((budget, policy));
+        self
+    }
+
+    /// Only emit frames with timestamps between the in and out point, e.g.
+    /// to extract a clip. Input before the in point is decoded from the last
+    /// keyframe preceding it, earlier input and input after the first
+    /// keyframe past the out point are dropped undecoded.
+    pub fn time_range(mut self, range: RangeInclusive<u64>) -> Self {
+        self.time_range = Some(range);
+        self
+    }
+
+    /// Decode synchronously inside [`push_data`](crate::Openh264Decoder::push_data)
+    /// and the service's `handle()`, without a background thread or channels,
+    /// for single-stream callers that are threaded already and want the lowest
+    /// latency. Decoding then blocks the calling task, so it should not be an
+    /// async runtime's worker. Overrides [`worker_pool`](Self::worker_pool).
+    /// Always the case on wasm32. Disabled by default.
+    pub fn inline(mut self, enabled: bool) -> Self {
+        self.inline = enabled;
+        self
+    }
+
+    /// Start the blocking worker on the given runtime or executor instead of
+    /// with `tokio::task::spawn_blocking` on the runtime the decoder happens
+    /// to be constructed on. Ignored with a [`worker_pool`](Self::worker_pool)
+    /// and [`inline`](Self::inline) decoding.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = Some(spawner);
+        self
+    }
+
+    /// Decode on a pool shared with other decoders instead of a dedicated blocking thread.
+    pub fn worker_pool(mut self, pool: WorkerPool) -> Self {
+        self.worker_pool = Some(pool);
+        self
+    }
+
+    /// Publish [`DecoderQos`](crate::DecoderQos) every `interval` into the
+    /// [`FeedbackBus`](crate::FeedbackBus) of the pipeline context, so
+    /// upstream components can adapt the stream they deliver. Disabled by
+    /// default.
+    pub fn qos(mut self, interval: Duration) -> Self {
+        self.qos = Some(interval);
+        self
+    }
+}
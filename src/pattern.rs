@@ -0,0 +1,249 @@
+use std::marker::PhantomData;
+
+use bytes::Bytes;
+use flowly::{DataFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, Service, VideoFrame};
+use futures::Stream;
+
+use crate::Error;
+
+/// Picture drawn by a [`TestPatternSource`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TestPattern {
+    /// Seven vertical bars at 75% intensity: white, yellow, cyan, green,
+    /// magenta, red and blue.
+    #[default]
+    ColorBars,
+    /// Diagonal gradient that shifts every frame, so inter frames carry motion.
+    Gradient,
+    /// White square moving across a black background, e.g. to measure
+    /// glass-to-glass latency.
+    MovingBox,
+    Black,
+}
+
+/// Configuration of a [`TestPatternSource`].
+#[derive(Debug, Clone)]
+pub struct TestPatternOptions {
+    pub(crate) pattern: TestPattern,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) frame_rate: f32,
+    pub(crate) timescale: u64,
+    pub(crate) start: u64,
+}
+
+impl Default for TestPatternOptions {
+    fn default() -> Self {
+        Self {
+            pattern: TestPattern::ColorBars,
+            width: 1280,
+            height: 720,
+            frame_rate: 30.0,
+            timescale: 1000,
+            start: 0,
+        }
+    }
+}
+
+impl TestPatternOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pattern(mut self, pattern: TestPattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// Defaults to 1280x720.
+    pub fn resolution(mut self, width: u16, height: u16) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Defaults to 30 fps.
+    pub fn frame_rate(mut self, fps: f32) -> Self {
+        self.frame_rate = fps;
+        self
+    }
+
+    /// Timestamp ticks per second, defaults to milliseconds.
+    pub fn timescale(mut self, timescale: u64) -> Self {
+        self.timescale = timescale;
+        self
+    }
+
+    /// Timestamp of the first frame, defaults to `0`.
+    pub fn start(mut self, timestamp: u64) -> Self {
+        self.start = timestamp;
+        self
+    }
+}
+
+/// Packed RGB frame produced by a [`TestPatternSource`], ready for an
+/// [`Openh264Encoder`](crate::Openh264Encoder) with its default input format.
+#[derive(Debug, Clone)]
+pub struct PatternFrame<S> {
+    pub timestamp: u64,
+    pub width: u16,
+    pub height: u16,
+    data: Bytes,
+    source: S,
+}
+
+/// Generator of raw video, so encoder pipelines, latency measurements and
+/// examples run without input files.
+///
+/// Iterate it for an endless stream, or use it as a service that yields the
+/// given number of frames per call. Frames are generated as fast as they are
+/// pulled, pacing them to the frame rate is up to the caller.
+pub struct TestPatternSource<S = ()> {
+    options: TestPatternOptions,
+    index: u64,
+    _source: PhantomData<fn() -> S>,
+}
+
+impl<S: Default> TestPatternSource<S> {
+    pub fn new(options: TestPatternOptions) -> Self {
+        Self {
+            options,
+            index: 0,
+            _source: PhantomData,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> PatternFrame<S> {
+        let TestPatternOptions {
+            width,
+            height,
+            frame_rate,
+            timescale,
+            start,
+            ..
+        } = self.options;
+
+        let offset = self.index as f64 * timescale as f64 / frame_rate.max(f32::EPSILON) as f64;
+        let data = draw(
+            self.options.pattern,
+            width as usize,
+            height as usize,
+            self.index,
+        );
+
+        self.index += 1;
+
+        PatternFrame {
+            timestamp: start + offset.round() as u64,
+            width,
+            height,
+            data: data.into(),
+            source: S::default(),
+        }
+    }
+}
+
+fn draw(pattern: TestPattern, width: usize, height: usize, index: u64) -> Vec<u8> {
+    const BARS: [[u8; 3]; 7] = [
+        [191, 191, 191],
+        [191, 191, 0],
+        [0, 191, 191],
+        [0, 191, 0],
+        [191, 0, 191],
+        [191, 0, 0],
+        [0, 0, 191],
+    ];
+
+    let mut data = vec![0; width * height * 3];
+    let shift = index as usize;
+
+    // a square of an eighth of the height, crossing the picture once every 4 seconds at 30 fps
+    let size = (height / 8).max(1);
+    let span = width.saturating_sub(size).max(1);
+    let box_x = shift * span / 120 % span;
+    let box_y = (height - size.min(height)) / 2;
+
+    for (pos, pixel) in data.chunks_exact_mut(3).enumerate() {
+        let (x, y) = (pos % width, pos / width);
+
+        let rgb = match pattern {
+            TestPattern::ColorBars => BARS[x * BARS.len() / width],
+            TestPattern::Gradient => [
+                ((x + shift * 2) * 255 / width % 256) as u8,
+                (y * 255 / height.max(1)) as u8,
+                ((x + y + shift * 4) % 256) as u8,
+            ],
+            TestPattern::MovingBox => {
+                let inside =
+                    (box_x..box_x + size).contains(&x) && (box_y..box_y + size).contains(&y);
+                if inside { [255; 3] } else { [0; 3] }
+            }
+            TestPattern::Black => [0; 3],
+        };
+
+        pixel.copy_from_slice(&rgb);
+    }
+
+    data
+}
+
+impl<S: Default> Iterator for TestPatternSource<S> {
+    type Item = PatternFrame<S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_frame())
+    }
+}
+
+impl<S: FrameSource + Default> Service<usize> for TestPatternSource<S> {
+    type Out = Result<PatternFrame<S>, Error>;
+
+    fn handle(&mut self, frames: usize, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            for _ in 0..frames {
+                yield Ok(self.next_frame());
+            }
+        }
+    }
+}
+
+impl<S: FrameSource> DataFrame for PatternFrame<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        std::iter::once(&self.data[..])
+    }
+
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        std::iter::once(self.data)
+    }
+}
+
+impl<S: FrameSource> Frame for PatternFrame<S> {
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn codec(&self) -> Fourcc {
+        Fourcc::PIXEL_FORMAT_RGB888
+    }
+
+    fn flags(&self) -> FrameFlags {
+        FrameFlags::VIDEO_STREAM
+    }
+}
+
+impl<S: FrameSource> VideoFrame for PatternFrame<S> {
+    fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn bit_depth(&self) -> u8 {
+        8
+    }
+}
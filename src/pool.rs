@@ -0,0 +1,269 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use flowly::spsc;
+
+use crate::{
+    Error, FrameBuffer,
+    worker::{Input, Worker},
+};
+
+/// Bounded set of threads shared by any number of decoders.
+///
+/// Decoders attached to a pool take turns: a worker thread decodes a single
+/// access unit of one decoder, then moves on to the next decoder with pending
+/// input. The threads exit once the pool and every decoder using it are dropped.
+#[derive(Clone)]
+pub struct WorkerPool {
+    shared: Arc<PoolShared>,
+}
+
+/// Owned by the handles only, so dropping the last one shuts the threads down.
+struct PoolShared {
+    executor: Executor,
+    threads: usize,
+}
+
+/// What runs the jobs of a pool.
+#[derive(Clone)]
+enum Executor {
+    Threads(Arc<PoolInner>),
+    /// A rayon pool, `None` for the global one.
+    #[cfg(feature = "rayon")]
+    Rayon(Option<Arc<rayon::ThreadPool>>),
+}
+
+struct PoolInner {
+    queue: Mutex<PoolQueue>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PoolQueue {
+    jobs: VecDeque<Arc<dyn Job>>,
+    shutdown: bool,
+}
+
+trait Job: Send + Sync {
+    fn run(self: Arc<Self>);
+
+    fn scheduled(&self) -> &AtomicBool;
+
+    fn executor(&self) -> &Executor;
+}
+
+impl WorkerPool {
+    /// Fails if the threads cannot be spawned, e.g. on wasm32.
+    pub fn new(threads: usize) -> Result<Self, Error> {
+        let threads = threads.max(1);
+        let inner = Arc::new(PoolInner {
+            queue: Mutex::new(PoolQueue::default()),
+            ready: Condvar::new(),
+        });
+
+        // threads already started shut down again with it if a later one fails
+        let pool = Self {
+            shared: Arc::new(PoolShared {
+                executor: Executor::Threads(inner.clone()),
+                threads,
+            }),
+        };
+
+        for idx in 0..threads {
+            let inner = inner.clone();
+
+            std::thread::Builder::new()
+                .name(format!("openh264-pool-{idx}"))
+                .spawn(move || inner.work())?;
+        }
+
+        Ok(pool)
+    }
+
+    /// Runs decode jobs on a rayon pool instead of threads of its own, for
+    /// applications that size a rayon pool for their CPU work already.
+    /// `None` uses rayon's global pool.
+    #[cfg(feature = "rayon")]
+    pub fn rayon(pool: Option<Arc<rayon::ThreadPool>>) -> Self {
+        let threads = pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| {
+                pool.current_num_threads()
+            });
+
+        Self {
+            shared: Arc::new(PoolShared {
+                executor: Executor::Rayon(pool),
+                threads,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn threads(&self) -> usize {
+        self.shared.threads
+    }
+
+    /// Hands a worker over to the pool.
+    pub(crate) fn attach<S: Send + Default + 'static, M: FrameBuffer>(
+        &self,
+        mut worker: Worker<S, M>,
+        rx: spsc::Receiver<Input<S>>,
+    ) -> TaskHandle {
+        worker.hold_output();
+
+        TaskHandle(Arc::new(Task {
+            state: Mutex::new(Some((worker, rx))),
+            scheduled: AtomicBool::new(false),
+            executor: self.shared.executor.clone(),
+        }))
+    }
+}
+
+impl std::fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("threads", &self.shared.threads)
+            .finish()
+    }
+}
+
+impl Drop for PoolShared {
+    fn drop(&mut self) {
+        if let Executor::Threads(inner) = &self.executor {
+            inner.queue.lock().unwrap().shutdown = true;
+            inner.ready.notify_all();
+        }
+    }
+}
+
+impl Executor {
+    fn schedule(&self, job: Arc<dyn Job>) {
+        match self {
+            Self::Threads(inner) => inner.schedule(job),
+            #[cfg(feature = "rayon")]
+            Self::Rayon(Some(pool)) => pool.spawn(move || job.run()),
+            #[cfg(feature = "rayon")]
+            Self::Rayon(None) => rayon::spawn(move || job.run()),
+        }
+    }
+}
+
+impl PoolInner {
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap();
+
+                loop {
+                    if let Some(job) = queue.jobs.pop_front() {
+                        break job;
+                    }
+
+                    if queue.shutdown {
+                        return;
+                    }
+
+                    queue = self.ready.wait(queue).unwrap();
+                }
+            };
+
+            job.run();
+        }
+    }
+
+    fn schedule(&self, job: Arc<dyn Job>) {
+        self.queue.lock().unwrap().jobs.push_back(job);
+        self.ready.notify_one();
+    }
+}
+
+/// Type-erased handle to a [`Task`], kept by the decoder it belongs to.
+pub(crate) struct TaskHandle(Arc<dyn Job>);
+
+impl TaskHandle {
+    /// Queues the task unless it is queued already; call after every push and on close.
+    #[inline]
+    pub(crate) fn notify(&self) {
+        notify(&self.0);
+    }
+}
+
+fn notify(job: &Arc<dyn Job>) {
+    if !job.scheduled().swap(true, Ordering::AcqRel) {
+        job.executor().schedule(job.clone());
+    }
+}
+
+/// A decoder worker living on a [`WorkerPool`].
+struct Task<S, M> {
+    state: Mutex<Option<(Worker<S, M>, spsc::Receiver<Input<S>>)>>,
+    scheduled: AtomicBool,
+    executor: Executor,
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> Job for Task<S, M> {
+    fn run(self: Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        let Some((worker, rx)) = state.as_mut() else {
+            return;
+        };
+
+        // cleared before polling, so a push or a pull racing with us re-queues us
+        self.scheduled.store(false, Ordering::Release);
+
+        // parked while the consumer has no room, it notifies us on every pull
+        if !worker.release_output() {
+            return;
+        }
+
+        if worker.is_finished() {
+            state.take();
+            return;
+        }
+
+        if worker.output_full() {
+            return;
+        }
+
+        let finished = match rx.try_recv() {
+            Ok(Some(input)) => {
+                if worker.receive(input) {
+                    drop(state);
+
+                    // one access unit per turn, then back of the queue
+                    notify(&(self as Arc<dyn Job>));
+                    return;
+                }
+
+                true
+            }
+            Ok(None) => false,
+            Err(_) => true,
+        };
+
+        if finished {
+            worker.finish();
+
+            // flushed frames may still be held, they go out on later pulls
+            if worker.release_output() {
+                state.take();
+            }
+        }
+    }
+
+    #[inline]
+    fn scheduled(&self) -> &AtomicBool {
+        &self.scheduled
+    }
+
+    #[inline]
+    fn executor(&self) -> &Executor {
+        &self.executor
+    }
+}
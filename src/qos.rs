@@ -0,0 +1,118 @@
+use std::{
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{Feedback, clock, memory::MemoryCounters};
+
+/// Decoder health over the last reporting interval, see [`DecoderOptions::qos`](crate::DecoderOptions::qos).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DecoderQos {
+    /// Frames decoded per second.
+    pub fps: f64,
+    /// Encoded bytes waiting for the decoder.
+    pub backlog: usize,
+    /// Fraction of frames lost to keyframe gating, decode errors or the
+    /// memory budget, placeholders included, `0.0..=1.0`.
+    pub drop_rate: f32,
+}
+
+/// Reports shared by the services of a pipeline, so upstream components such
+/// as a network fetcher or an ABR selector can adapt to what happens
+/// downstream. Put one into the [`flowly::Context`] the pipeline runs with.
+#[derive(Debug, Clone, Default)]
+pub struct FeedbackBus {
+    decoder: Arc<Mutex<Option<DecoderQos>>>,
+    delivery: Arc<Mutex<Option<Feedback>>>,
+}
+
+impl FeedbackBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The bus of the pipeline `cx` belongs to, if it has one.
+    pub(crate) fn of(cx: &flowly::Context) -> Option<&Self> {
+        cx.get::<Self>()
+    }
+
+    /// The last decoder report, `None` until the first interval has passed.
+    pub fn decoder_qos(&self) -> Option<DecoderQos> {
+        *self.decoder.lock().unwrap()
+    }
+
+    pub(crate) fn publish_decoder_qos(&self, qos: DecoderQos) {
+        *self.decoder.lock().unwrap() = Some(qos);
+    }
+
+    /// Reports delivery conditions for an [`AbrController`](crate::AbrController),
+    /// replacing any report it has not consumed yet.
+    pub fn report_delivery(&self, feedback: Feedback) {
+        *self.delivery.lock().unwrap() = Some(feedback);
+    }
+
+    pub(crate) fn take_delivery(&self) -> Option<Feedback> {
+        self.delivery.lock().unwrap().take()
+    }
+}
+
+/// Counts the frames a decoder worker decodes and drops, and leaves a report
+/// in its counters every interval for the decoder to publish. Never reports
+/// on wasm32, which has no clock.
+pub(crate) struct QosMeter {
+    interval: Duration,
+    started: Option<Instant>,
+    frames: u64,
+    dropped: u64,
+    /// Worker drops counted when the interval started.
+    worker_dropped: u64,
+}
+
+impl QosMeter {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            started: clock::now(),
+            frames: 0,
+            dropped: 0,
+            worker_dropped: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, synthetic: bool) {
+        if synthetic {
+            self.dropped += 1;
+        } else {
+            self.frames += 1;
+        }
+    }
+
+    /// Called for every access unit, decoded or not, so a decoder dropping
+    /// everything still reports.
+    pub(crate) fn poll(&mut self, counters: &MemoryCounters) {
+        let Some(started) = self.started else {
+            return;
+        };
+
+        let elapsed = started.elapsed();
+
+        if elapsed < self.interval {
+            return;
+        }
+
+        let worker_dropped = counters.dropped.load(Ordering::Relaxed);
+        let dropped = self.dropped + worker_dropped - self.worker_dropped;
+        let total = (self.frames + dropped).max(1);
+
+        *counters.qos.lock().unwrap() = Some(DecoderQos {
+            fps: self.frames as f64 / elapsed.as_secs_f64(),
+            backlog: counters.input_queue.load(Ordering::Relaxed),
+            drop_rate: dropped as f32 / total as f32,
+        });
+
+        self.started = clock::now();
+        self.frames = 0;
+        self.dropped = 0;
+        self.worker_dropped = worker_dropped;
+    }
+}
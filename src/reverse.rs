@@ -0,0 +1,146 @@
+use std::cmp::Reverse;
+
+use bytes::Bytes;
+use flowly::{DataFrame, EncodedFrame, Frame, MemBlock, Service};
+use futures::Stream;
+
+use crate::{
+    CodecConfig, DecodedFrame, DecoderOptions, Error, FrameBuffer, Openh264Decoder,
+    codec_config::ConfigTracker, nal,
+};
+
+type Gop<S> = Vec<(Bytes, u64, S)>;
+type Positioned<S, M> = (u64, Result<DecodedFrame<S, M>, Error>);
+
+/// Decoder for reverse playback that buffers input up to the next keyframe,
+/// decodes the GOP forward and yields its frames in reverse timestamp order.
+///
+/// Feed GOPs in the order they should play, e.g. last to first, each in
+/// decode order. Decoded frames of one GOP are cached at once, so memory
+/// grows with the GOP length. Call [`flush`](Self::flush) after the last
+/// frame to get the final GOP.
+pub struct ReverseDecoder<S, M = Vec<u8>> {
+    options: DecoderOptions,
+    gop: Gop<S>,
+    /// Latest parameter sets, fed ahead of every GOP.
+    config: Option<CodecConfig>,
+    tracker: ConfigTracker,
+    _buffer: std::marker::PhantomData<fn() -> M>,
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> ReverseDecoder<S, M> {
+    pub fn new() -> Self {
+        Self::with_options(DecoderOptions::default())
+    }
+
+    /// `options` apply to the decoder set up for every GOP.
+    pub fn with_options(options: DecoderOptions) -> Self {
+        Self {
+            options,
+            gop: Vec::new(),
+            config: None,
+            tracker: ConfigTracker::default(),
+            _buffer: std::marker::PhantomData,
+        }
+    }
+
+    /// Decodes the pending GOP and yields its frames.
+    pub fn flush(&mut self) -> impl Stream<Item = Result<DecodedFrame<S, M>, Error>> + '_ {
+        async_stream::stream! {
+            for res in self.decode_gop().await {
+                yield res;
+            }
+        }
+    }
+
+    async fn decode_gop(&mut self) -> Vec<Result<DecodedFrame<S, M>, Error>> {
+        if self.gop.is_empty() {
+            return Vec::new();
+        }
+
+        let gop = std::mem::take(&mut self.gop);
+        let start = gop[0].1;
+        let mut last = start;
+
+        let mut decoder = Openh264Decoder::with_options(self.options.clone());
+        let mut out = Vec::new();
+
+        for (data, timestamp, source) in gop {
+            last = timestamp;
+
+            if let Err(err) = decoder.push_data(data, timestamp, source).await {
+                out.push(Self::position(start, timestamp, Err(err)));
+                break;
+            }
+
+            while let Some(res) = decoder.pull_frame().transpose() {
+                out.push(Self::position(start, timestamp, res));
+            }
+        }
+
+        decoder.close();
+
+        while let Some(res) = decoder.recv_frame().await {
+            out.push(Self::position(start, last, res));
+        }
+
+        out.sort_by_key(|(timestamp, _)| Reverse(*timestamp));
+        out.into_iter().map(|(_, res)| res).collect()
+    }
+
+    /// Sorts errors in at the access unit they were raised for, which is
+    /// lost once reversed, so it goes into the error as well.
+    fn position(
+        start: u64,
+        timestamp: u64,
+        res: Result<DecodedFrame<S, M>, Error>,
+    ) -> Positioned<S, M> {
+        match res {
+            Ok(frame) => (frame.timestamp, Ok(frame)),
+            Err(err) => (
+                timestamp,
+                Err(Error::GopDecode(start, timestamp, Box::new(err))),
+            ),
+        }
+    }
+}
+
+impl<S: Send + Default + 'static, M: FrameBuffer> Default for ReverseDecoder<S, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: EncodedFrame + 'static, M: FrameBuffer> Service<F> for ReverseDecoder<F::Source, M> {
+    type Out = Result<DecodedFrame<F::Source, M>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let ts = frame.timestamp();
+            let source = frame.source().clone();
+            let chunks: Vec<Bytes> = frame.into_chunks().map(|c| c.into_cpu_bytes()).collect();
+
+            // recovery points still reference frames of the previous GOP
+            if chunks.iter().any(|chunk| nal::has_idr(chunk)) {
+                for res in self.decode_gop().await {
+                    yield res;
+                }
+            }
+
+            for chunk in chunks {
+                if let Some(config) = self.tracker.update(&chunk) {
+                    self.config = Some(config);
+                }
+
+                // each GOP decodes on a fresh instance that has not seen the
+                // parameter sets, which may have been sent only once
+                let chunk = match &self.config {
+                    Some(config) if self.gop.is_empty() => config.prepend_to(&chunk),
+                    _ => chunk,
+                };
+
+                self.gop.push((chunk, ts, source.clone()));
+            }
+        }
+    }
+}
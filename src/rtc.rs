@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use flowly::{DataFrame, EncodedFrame, Frame, FrameFlags, MemBlock, Service};
+use futures::Stream;
+use webrtc::{
+    media::Sample,
+    rtp::{codecs::h264::H264Packet, packet::Packet, packetizer::Depacketizer},
+};
+
+use crate::{
+    CapturedFrame, Error,
+    nal::{self, AccessUnitKind},
+};
+
+/// Builds an Annex B sample for `TrackLocalStaticSample::write_sample` from an
+/// encoded frame, e.g. the output of [`Openh264Encoder`](crate::Openh264Encoder)
+/// with any [`Chunking`](crate::Chunking).
+/// `duration` advances the RTP timestamp, usually one frame interval.
+pub fn to_sample<F: EncodedFrame>(frame: F, duration: Duration) -> Sample {
+    let mut data = BytesMut::new();
+
+    for chunk in frame.into_chunks() {
+        let chunk = chunk.into_cpu_bytes();
+
+        // NAL unit chunking strips the start codes the payloader splits on
+        if !chunk.starts_with(&[0, 0, 1]) && !chunk.starts_with(&[0, 0, 0, 1]) {
+            data.extend_from_slice(&[0, 0, 0, 1]);
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Sample {
+        data: data.freeze(),
+        duration,
+        ..Default::default()
+    }
+}
+
+/// Wraps an Annex B sample, e.g. from a `SampleBuilder` over a received track,
+/// as an encoded frame for the decoder, timestamped with its RTP timestamp.
+///
+/// The dimensions are left at zero, the decoder takes them from the stream.
+pub fn from_sample<S>(sample: &Sample, source: S) -> CapturedFrame<S> {
+    captured(sample.data.clone(), sample.packet_timestamp, source)
+}
+
+fn captured<S>(data: Bytes, timestamp: u32, source: S) -> CapturedFrame<S> {
+    let mut flags = FrameFlags::VIDEO_STREAM;
+
+    if nal::classify(&data) == AccessUnitKind::Keyframe {
+        flags |= FrameFlags::KEYFRAME;
+    }
+
+    CapturedFrame {
+        timestamp: timestamp as u64,
+        flags,
+        width: 0,
+        height: 0,
+        chunks: vec![data],
+        source,
+    }
+}
+
+/// Reassembles RTP packets of a received H.264 track into access units,
+/// yielding one encoded frame per marker bit, timestamped like
+/// [`from_sample`].
+///
+/// Packets must arrive in order, e.g. after a jitter buffer. A packet that
+/// fails to depacketize drops the access unit it belongs to.
+pub struct RtpDepacketizer<S> {
+    depacketizer: H264Packet,
+    pending: BytesMut,
+    /// Set after a broken packet until the end of its access unit.
+    broken: bool,
+    source: S,
+}
+
+impl<S: Clone> RtpDepacketizer<S> {
+    /// `source` is attached to every frame.
+    pub fn new(source: S) -> Self {
+        Self {
+            depacketizer: H264Packet::default(),
+            pending: BytesMut::new(),
+            broken: false,
+            source,
+        }
+    }
+
+    /// Feeds one packet, returns the access unit it completes.
+    pub fn push(&mut self, packet: &Packet) -> Result<Option<CapturedFrame<S>>, Error> {
+        match self.depacketizer.depacketize(&packet.payload) {
+            Ok(data) if !self.broken => self.pending.extend_from_slice(&data),
+            Ok(_) => (),
+            Err(err) => {
+                self.pending.clear();
+                self.broken = !packet.header.marker;
+
+                return Err(err.into());
+            }
+        }
+
+        if !packet.header.marker {
+            return Ok(None);
+        }
+
+        let broken = std::mem::take(&mut self.broken);
+        let data = self.pending.split().freeze();
+
+        if broken || data.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(captured(
+            data,
+            packet.header.timestamp,
+            self.source.clone(),
+        )))
+    }
+}
+
+impl<S: Clone + Send> Service<Packet> for RtpDepacketizer<S> {
+    type Out = Result<CapturedFrame<S>, Error>;
+
+    fn handle(&mut self, packet: Packet, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            if let Some(res) = self.push(&packet).transpose() {
+                yield res;
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use bytes::Bytes;
+use flowly::Service;
+use futures::Stream;
+
+use crate::{
+    CodecConfig, DecodedFrame, DecoderOptions, Error, FrameBuffer, Openh264Decoder,
+    codec_config::ConfigTracker,
+    nal::{self, AccessUnitKind},
+};
+
+/// Keyframe an [`ExactSeeker`] can start decoding at.
+struct SeekPoint {
+    index: usize,
+    timestamp: u64,
+    /// Parameter sets in effect, fed ahead of the keyframe.
+    config: Option<CodecConfig>,
+}
+
+/// Frame-accurate seeking in a clip held in memory, e.g. for scrubbing.
+///
+/// [`seek`](Self::seek) decodes from the nearest keyframe at or before the
+/// target and discards frames until the target is reached. As a service it
+/// takes target timestamps and yields the frame for each.
+pub struct ExactSeeker<S, M = Vec<u8>> {
+    options: DecoderOptions,
+    units: Vec<(Bytes, u64, S)>,
+    points: Vec<SeekPoint>,
+    config: Option<CodecConfig>,
+    tracker: ConfigTracker,
+    _buffer: std::marker::PhantomData<fn() -> M>,
+}
+
+impl<S: Clone + Send + Default + 'static, M: FrameBuffer> ExactSeeker<S, M> {
+    /// `options` apply to the decoder set up for every seek.
+    pub fn new(options: DecoderOptions) -> Self {
+        Self {
+            options,
+            units: Vec::new(),
+            points: Vec::new(),
+            config: None,
+            tracker: ConfigTracker::default(),
+            _buffer: std::marker::PhantomData,
+        }
+    }
+
+    /// Appends an access unit of the clip, in decode order.
+    pub fn push(&mut self, data: Bytes, timestamp: u64, source: S) {
+        if let Some(config) = self.tracker.update(&data) {
+            self.config = Some(config);
+        }
+
+        if nal::classify(&data) == AccessUnitKind::Keyframe {
+            self.points.push(SeekPoint {
+                index: self.units.len(),
+                timestamp,
+                config: self.config.clone(),
+            });
+        }
+
+        self.units.push((data, timestamp, source));
+    }
+
+    /// Decodes the frame with timestamp `target`, or the first one after it
+    /// if there is none. `None` if the clip ends before `target`.
+    pub async fn seek(&mut self, target: u64) -> Result<Option<DecodedFrame<S, M>>, Error> {
+        let point = self
+            .points
+            .iter()
+            .rposition(|point| point.timestamp <= target);
+        let start = point.map_or(0, |idx| self.points[idx].index);
+
+        let options = self.options.clone().time_range(target..=u64::MAX);
+        let mut decoder = Openh264Decoder::<S, M>::try_with_options(options)?;
+        let config = point.and_then(|idx| self.points[idx].config.as_ref());
+
+        // a target after the last frame of its GOP is answered by a later GOP
+        for (pos, (data, timestamp, source)) in self.units[start..].iter().enumerate() {
+            let data = match config {
+                Some(config) if pos == 0 => config.prepend_to(data),
+                _ => data.clone(),
+            };
+
+            decoder.push_data(data, *timestamp, source.clone()).await?;
+
+            if let Some(frame) = decoder.pull_frame()? {
+                return Ok(Some(frame));
+            }
+        }
+
+        decoder.close();
+        decoder.recv_frame().await.transpose()
+    }
+}
+
+impl<S: Clone + Send + Default + 'static, M: FrameBuffer> Service<u64> for ExactSeeker<S, M> {
+    type Out = Result<DecodedFrame<S, M>, Error>;
+
+    fn handle(&mut self, target: u64, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            if let Some(res) = self.seek(target).await.transpose() {
+                yield res;
+            }
+        }
+    }
+}
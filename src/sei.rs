@@ -0,0 +1,156 @@
+use bytes::Bytes;
+
+use crate::nal;
+
+/// User data unregistered SEI message (payload type 5), an application
+/// defined payload tagged with a UUID, e.g. frame IDs, analytics data or
+/// synchronization markers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserDataSei {
+    pub uuid: [u8; 16],
+    pub payload: Bytes,
+}
+
+impl UserDataSei {
+    pub fn new(uuid: [u8; 16], payload: impl Into<Bytes>) -> Self {
+        Self {
+            uuid,
+            payload: payload.into(),
+        }
+    }
+}
+
+/// Frames a periodic SEI message is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeiSchedule {
+    EveryFrame,
+    /// Keyframes only, so every decodable entry point carries the message.
+    Keyframes,
+    /// Every n-th input frame, starting with the first.
+    Interval(u64),
+}
+
+impl SeiSchedule {
+    #[inline]
+    pub(crate) fn matches(&self, frame_index: u64, keyframe: bool) -> bool {
+        match *self {
+            Self::EveryFrame => true,
+            Self::Keyframes => keyframe,
+            Self::Interval(n) => frame_index % n.max(1) == 0,
+        }
+    }
+}
+
+pub(crate) const PIC_TIMING: u32 = 1;
+pub(crate) const USER_DATA_UNREGISTERED: u32 = 5;
+
+/// Builds a SEI NAL unit, without start code, carrying the given
+/// `(payload type, payload)` messages.
+pub(crate) fn sei_nal<'a>(messages: impl IntoIterator<Item = (u32, &'a [u8])>) -> Vec<u8> {
+    let mut rbsp = Vec::new();
+
+    for (payload_type, payload) in messages {
+        write_sei_value(&mut rbsp, payload_type as usize);
+        write_sei_value(&mut rbsp, payload.len());
+        rbsp.extend_from_slice(payload);
+    }
+
+    // rbsp_trailing_bits
+    rbsp.push(0x80);
+
+    let mut out = vec![0x06];
+    out.extend(nal::from_rbsp(&rbsp));
+    out
+}
+
+/// Splits a SEI NAL unit into its `(payload type, payload)` messages,
+/// `None` if it is truncated.
+pub(crate) fn parse_sei(nal: &[u8]) -> Option<Vec<(u32, Vec<u8>)>> {
+    let rbsp = nal::to_rbsp(nal.get(1..)?);
+    let mut messages = Vec::new();
+    let mut pos = 0;
+
+    // more_rbsp_data: anything before the trailing bits
+    while pos < rbsp.len() && rbsp[pos..] != [0x80] {
+        let payload_type = read_sei_value(&rbsp, &mut pos)?;
+        let payload_size = read_sei_value(&rbsp, &mut pos)?;
+        let payload = rbsp.get(pos..pos + payload_size)?;
+
+        messages.push((payload_type as u32, payload.to_vec()));
+        pos += payload_size;
+    }
+
+    Some(messages)
+}
+
+/// Payload of a user data unregistered message.
+pub(crate) fn user_data_payload(sei: &UserDataSei) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(16 + sei.payload.len());
+
+    payload.extend_from_slice(&sei.uuid);
+    payload.extend_from_slice(&sei.payload);
+    payload
+}
+
+fn read_sei_value(rbsp: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value = 0;
+
+    loop {
+        let byte = *rbsp.get(*pos)?;
+        *pos += 1;
+        value += byte as usize;
+
+        if byte != 0xff {
+            return Some(value);
+        }
+    }
+}
+
+fn write_sei_value(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0xff {
+        out.push(0xff);
+        value -= 0xff;
+    }
+
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sei_roundtrip() {
+        let long: Vec<u8> = (0..300).map(|idx| (idx % 7) as u8 * 40).collect();
+        let zeroes = [0, 0, 0, 1];
+        let nal = sei_nal([
+            (USER_DATA_UNREGISTERED, &long[..]),
+            (PIC_TIMING, &zeroes[..]),
+        ]);
+
+        assert_eq!(nal[0], 0x06);
+        assert!(!nal.windows(3).any(|w| w[0] == 0 && w[1] == 0 && w[2] <= 2));
+        assert_eq!(
+            parse_sei(&nal),
+            Some(vec![
+                (USER_DATA_UNREGISTERED, long),
+                (PIC_TIMING, zeroes.to_vec()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn parse_sei_rejects_truncated_payload() {
+        let nal = sei_nal([(USER_DATA_UNREGISTERED, &[1; 20][..])]);
+
+        assert_eq!(parse_sei(&nal[..nal.len() - 5]), None);
+        assert_eq!(parse_sei(&[0x06]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn user_data_payload_starts_with_uuid() {
+        let sei = UserDataSei::new([7; 16], &b"frame"[..]);
+
+        assert_eq!(user_data_payload(&sei), [&[7; 16][..], b"frame"].concat());
+    }
+}
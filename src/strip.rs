@@ -0,0 +1,187 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly::{DataFrame, EncodedFrame, Frame, MemBlock, Service, VideoFrame};
+use futures::Stream;
+
+use crate::{
+    CapturedFrame, Error,
+    nal::{self, NalType},
+    sei,
+};
+
+/// SEI messages a [`NalFilter`] removes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SeiFilter {
+    #[default]
+    Keep,
+    /// Every SEI NAL unit, recovery points included.
+    All,
+    /// Messages of the given payload types, e.g. `5` for user data
+    /// unregistered. SEI NAL units left empty are dropped.
+    PayloadTypes(Vec<u32>),
+}
+
+/// Removes access unit delimiters, filler data, selected SEI messages and SVC
+/// enhancement layers from Annex B input.
+///
+/// Set with [`DecoderOptions::nal_filter`](crate::DecoderOptions::nal_filter)
+/// to filter in front of the decoder, or used as a service to filter encoded
+/// frames that are forwarded elsewhere. Keeps everything by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NalFilter {
+    pub(crate) aud: bool,
+    pub(crate) filler: bool,
+    pub(crate) sei: SeiFilter,
+    pub(crate) svc_base_layer: bool,
+    pub(crate) max_temporal_id: Option<u8>,
+}
+
+impl NalFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn strip_aud(mut self, enabled: bool) -> Self {
+        self.aud = enabled;
+        self
+    }
+
+    pub fn strip_filler(mut self, enabled: bool) -> Self {
+        self.filler = enabled;
+        self
+    }
+
+    pub fn strip_sei(mut self, sei: SeiFilter) -> Self {
+        self.sei = sei;
+        self
+    }
+
+    /// Keep only the AVC compatible base layer of scalable streams, dropping
+    /// prefix NAL units, subset SPS and slice extensions, i.e. every spatial
+    /// and quality enhancement layer, for a lower fidelity but valid picture.
+    pub fn svc_base_layer(mut self, enabled: bool) -> Self {
+        self.svc_base_layer = enabled;
+        self
+    }
+
+    /// Drop base layer slices above temporal layer `id`, as signalled by their
+    /// SVC prefix NAL units, e.g. `0` for the lowest frame rate. Streams
+    /// without prefix NAL units are not affected.
+    pub fn max_temporal_id(mut self, id: Option<u8>) -> Self {
+        self.max_temporal_id = id;
+        self
+    }
+
+    #[inline]
+    fn is_active(&self) -> bool {
+        self.aud
+            || self.filler
+            || self.sei != SeiFilter::Keep
+            || self.svc_base_layer
+            || self.max_temporal_id.is_some()
+    }
+
+    /// Filters an Annex B access unit, `None` if nothing was removed.
+    /// Data without start codes is left alone.
+    pub(crate) fn apply(&self, data: &[u8]) -> Option<Bytes> {
+        if !self.is_active() {
+            return None;
+        }
+
+        let mut changed = false;
+        let mut out = BytesMut::with_capacity(data.len());
+        // set by a prefix NAL unit for the base layer slice it precedes
+        let mut above_temporal_id = false;
+
+        for unit in nal::nal_units(data) {
+            let kept = match NalType::from_header(unit[0]) {
+                NalType::Aud if self.aud => None,
+                NalType::Filler if self.filler => None,
+                NalType::Prefix => {
+                    // temporal_id is the top three bits of the third extension byte
+                    above_temporal_id = self
+                        .max_temporal_id
+                        .zip(unit.get(3))
+                        .is_some_and(|(max, ext)| ext >> 5 > max);
+
+                    (!self.svc_base_layer).then(|| Bytes::copy_from_slice(unit))
+                }
+                NalType::SubsetSps | NalType::SliceExtension if self.svc_base_layer => None,
+                NalType::Slice | NalType::IdrSlice => {
+                    // a prefix NAL unit only describes the slice right after it
+                    let above = std::mem::take(&mut above_temporal_id);
+
+                    (!above).then(|| Bytes::copy_from_slice(unit))
+                }
+                NalType::Sei => self.filter_sei(unit),
+                _ => Some(Bytes::copy_from_slice(unit)),
+            };
+
+            match kept {
+                Some(kept) => {
+                    changed |= kept != unit;
+                    out.put_slice(&[0, 0, 0, 1]);
+                    out.put_slice(&kept);
+                }
+                None => changed = true,
+            }
+        }
+
+        changed.then(|| out.freeze())
+    }
+
+    fn filter_sei(&self, unit: &[u8]) -> Option<Bytes> {
+        let types = match &self.sei {
+            SeiFilter::Keep => return Some(Bytes::copy_from_slice(unit)),
+            SeiFilter::All => return None,
+            SeiFilter::PayloadTypes(types) => types,
+        };
+
+        let Some(messages) = sei::parse_sei(unit) else {
+            // leave malformed SEI to the decoder
+            return Some(Bytes::copy_from_slice(unit));
+        };
+
+        if !messages.iter().any(|(ty, _)| types.contains(ty)) {
+            return Some(Bytes::copy_from_slice(unit));
+        }
+
+        let kept: Vec<_> = messages
+            .iter()
+            .filter(|(ty, _)| !types.contains(ty))
+            .map(|(ty, payload)| (*ty, &payload[..]))
+            .collect();
+
+        (!kept.is_empty()).then(|| sei::sei_nal(kept).into())
+    }
+}
+
+impl<F: EncodedFrame + VideoFrame + 'static> Service<F> for NalFilter {
+    type Out = Result<CapturedFrame<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let (width, height) = frame.dimensions();
+            let timestamp = frame.timestamp();
+            let flags = frame.flags();
+            let source = frame.source().clone();
+
+            let chunks = frame
+                .into_chunks()
+                .map(|chunk| chunk.into_cpu_bytes())
+                .filter_map(|chunk| match self.apply(&chunk) {
+                    Some(filtered) => (!filtered.is_empty()).then_some(filtered),
+                    None => Some(chunk),
+                })
+                .collect();
+
+            yield Ok(CapturedFrame {
+                timestamp,
+                flags,
+                width,
+                height,
+                chunks,
+                source,
+            });
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use bytes::Bytes;
+use flowly::{DataFrame, EncodedFrame, Frame, MemBlock, Service, VideoFrame};
+use futures::Stream;
+
+use crate::{CapturedFrame, DecodedFrame, Error, FrameBuffer, Openh264Decoder};
+
+/// Item of a [`TeeDecoder`].
+#[derive(Debug)]
+pub enum TeeOutput<S, M> {
+    /// An input frame as it was pushed, emitted before any frame decoded from it.
+    Encoded(CapturedFrame<S>),
+    Decoded(DecodedFrame<S, M>),
+}
+
+/// Decoder service that emits every encoded input frame alongside the frames
+/// decoded from it, e.g. to record the original bitstream while displaying
+/// it without splitting the pipeline in front of the decoder.
+///
+/// Decoded frames may come out a few inputs later than their encoded frame,
+/// match them up by timestamp.
+pub struct TeeDecoder<S, M = Vec<u8>> {
+    decoder: Openh264Decoder<S, M>,
+}
+
+impl<S, M> TeeDecoder<S, M> {
+    pub fn new(decoder: Openh264Decoder<S, M>) -> Self {
+        Self { decoder }
+    }
+
+    pub fn into_inner(self) -> Openh264Decoder<S, M> {
+        self.decoder
+    }
+}
+
+impl<S, M> std::ops::Deref for TeeDecoder<S, M> {
+    type Target = Openh264Decoder<S, M>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.decoder
+    }
+}
+
+impl<S, M> std::ops::DerefMut for TeeDecoder<S, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.decoder
+    }
+}
+
+impl<F: EncodedFrame + VideoFrame + 'static, M: FrameBuffer> Service<F>
+    for TeeDecoder<F::Source, M>
+{
+    type Out = Result<TeeOutput<F::Source, M>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &flowly::Context) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            let (width, height) = frame.dimensions();
+            let timestamp = frame.timestamp();
+            let flags = frame.flags();
+            let source = frame.source().clone();
+
+            let chunks: Vec<Bytes> = frame
+                .into_chunks()
+                .map(|chunk| chunk.into_cpu_bytes())
+                .collect();
+
+            for chunk in &chunks {
+                if let Err(err) = self.decoder.push_data(chunk.clone(), timestamp, source.clone()).await {
+                    yield Err(err);
+                }
+            }
+
+            yield Ok(TeeOutput::Encoded(CapturedFrame {
+                timestamp,
+                flags,
+                width,
+                height,
+                chunks,
+                source,
+            }));
+
+            while let Some(res) = self.decoder.pull_frame().transpose() {
+                yield res.map(TeeOutput::Decoded);
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    bits::{BitReader, BitWriter},
+    nal,
+};
+
+/// Where the encoder takes the timecode carried in picture timing SEI from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimecodeSource {
+    /// The input timestamp, with `timescale` ticks per second.
+    Timestamps { timescale: u64 },
+    /// Time of day (UTC) when the frame is encoded.
+    WallClock,
+}
+
+/// A full `clock_timestamp` of a picture timing SEI message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Timecode {
+    hours: u8,
+    minutes: u8,
+    seconds: u8,
+    frames: u8,
+}
+
+impl TimecodeSource {
+    pub(crate) fn timecode(&self, timestamp: u64, frame_rate: f32) -> Timecode {
+        let (seconds, fraction) = match *self {
+            Self::Timestamps { timescale } => {
+                let timescale = timescale.max(1);
+
+                (
+                    timestamp / timescale,
+                    (timestamp % timescale) as f64 / timescale as f64,
+                )
+            }
+            Self::WallClock => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+
+                (now.as_secs(), now.subsec_nanos() as f64 / 1e9)
+            }
+        };
+
+        let max_frames = (frame_rate.max(1.0).ceil() as u64).min(256);
+
+        Timecode {
+            hours: (seconds / 3600 % 24) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            frames: ((fraction * frame_rate as f64) as u64).min(max_frames - 1) as u8,
+        }
+    }
+}
+
+impl Timecode {
+    /// Picture timing payload for a progressive frame, for a stream without
+    /// HRD parameters as produced by [`with_pic_struct`].
+    pub(crate) fn pic_timing_payload(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+
+        w.write_bits(0, 4); // pic_struct: frame
+        w.write_bit(true); // clock_timestamp_flag
+        w.write_bits(0, 2); // ct_type: progressive
+        w.write_bit(true); // nuit_field_based_flag
+        w.write_bits(0, 5); // counting_type: no dropped frames
+        w.write_bit(true); // full_timestamp_flag
+        w.write_bit(false); // discontinuity_flag
+        w.write_bit(false); // cnt_dropped_flag
+        w.write_bits(self.frames as u32, 8);
+        w.write_bits(self.seconds as u32, 6);
+        w.write_bits(self.minutes as u32, 6);
+        w.write_bits(self.hours as u32, 5);
+        w.write_bits(0, 24); // time_offset, time_offset_length is inferred as 24
+
+        if w.is_aligned() {
+            w.into_bytes()
+        } else {
+            w.finish()
+        }
+    }
+}
+
+/// Rewrites an SPS NAL unit so its VUI signals `pic_struct_present_flag`,
+/// without which decoders ignore picture timing SEI. openh264 never sets it.
+///
+/// `None` if the SPS can not be parsed or carries HRD parameters, whose
+/// delays this crate does not track.
+pub(crate) fn with_pic_struct(sps: &[u8]) -> Option<Vec<u8>> {
+    let rbsp = nal::to_rbsp(sps.get(1..)?);
+    let mut c = Copier {
+        r: BitReader::new(&rbsp),
+        w: BitWriter::default(),
+    };
+
+    let profile_idc = c.bits(8)?;
+    c.bits(16)?; // constraint flags and level_idc
+    c.ue()?; // seq_parameter_set_id
+
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        let chroma_format_idc = c.ue()?;
+
+        if chroma_format_idc == 3 {
+            c.flag()?; // separate_colour_plane_flag
+        }
+
+        c.ue()?; // bit_depth_luma_minus8
+        c.ue()?; // bit_depth_chroma_minus8
+        c.flag()?; // qpprime_y_zero_transform_bypass_flag
+
+        if c.flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+
+            for idx in 0..lists {
+                if c.flag()? {
+                    c.scaling_list(if idx < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    c.ue()?; // log2_max_frame_num_minus4
+
+    match c.ue()? {
+        0 => {
+            c.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            c.flag()?; // delta_pic_order_always_zero_flag
+            c.se()?; // offset_for_non_ref_pic
+            c.se()?; // offset_for_top_to_bottom_field
+
+            for _ in 0..c.ue()? {
+                c.se()?; // offset_for_ref_frame
+            }
+        }
+        _ => (),
+    }
+
+    c.ue()?; // max_num_ref_frames
+    c.flag()?; // gaps_in_frame_num_value_allowed_flag
+    c.ue()?; // pic_width_in_mbs_minus1
+    c.ue()?; // pic_height_in_map_units_minus1
+
+    if !c.flag()? {
+        c.flag()?; // mb_adaptive_frame_field_flag
+    }
+
+    c.flag()?; // direct_8x8_inference_flag
+
+    if c.flag()? {
+        for _ in 0..4 {
+            c.ue()?; // frame_crop_*_offset
+        }
+    }
+
+    let vui = c.r.read_bit()?;
+    c.w.write_bit(true);
+
+    if vui {
+        c.vui()?;
+    } else {
+        // no aspect ratio, overscan, signal type, chroma location, timing or HRD
+        c.w.write_bits(0, 7);
+        c.w.write_bit(true);
+        c.w.write_bit(false); // bitstream_restriction_flag
+    }
+
+    let mut out = vec![sps[0]];
+    out.extend(nal::from_rbsp(&c.w.finish()));
+
+    Some(out)
+}
+
+/// Copies syntax elements from the reader to the writer as they are parsed.
+struct Copier<'a> {
+    r: BitReader<'a>,
+    w: BitWriter,
+}
+
+impl Copier<'_> {
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let value = self.r.read_bits(count)?;
+        self.w.write_bits(value, count);
+        Some(value)
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        let bit = self.r.read_bit()?;
+        self.w.write_bit(bit);
+        Some(bit)
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let value = self.r.read_ue()?;
+        self.w.write_ue(value);
+        Some(value)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let value = self.r.read_se()?;
+        self.w.write_se(value);
+        Some(value)
+    }
+
+    fn scaling_list(&mut self, size: usize) -> Option<()> {
+        let (mut last, mut next) = (8, 8);
+
+        for _ in 0..size {
+            if next != 0 {
+                next = (last + self.se()? + 256) % 256;
+            }
+
+            if next != 0 {
+                last = next;
+            }
+        }
+
+        Some(())
+    }
+
+    fn vui(&mut self) -> Option<()> {
+        // aspect_ratio_info_present_flag, with extended SAR
+        if self.flag()? && self.bits(8)? == 255 {
+            self.bits(32)?;
+        }
+
+        // overscan_info_present_flag
+        if self.flag()? {
+            self.flag()?;
+        }
+
+        // video_signal_type_present_flag
+        if self.flag()? {
+            self.bits(4)?;
+
+            if self.flag()? {
+                self.bits(24)?; // colour primaries, transfer and matrix
+            }
+        }
+
+        // chroma_loc_info_present_flag
+        if self.flag()? {
+            self.ue()?;
+            self.ue()?;
+        }
+
+        // timing_info_present_flag
+        if self.flag()? {
+            self.bits(32)?;
+            self.bits(32)?;
+            self.flag()?;
+        }
+
+        // nal and vcl hrd_parameters_present_flag
+        if self.flag()? || self.flag()? {
+            return None;
+        }
+
+        self.r.read_bit()?;
+        self.w.write_bit(true); // pic_struct_present_flag
+
+        // bitstream_restriction_flag
+        if self.flag()? {
+            self.flag()?; // motion_vectors_over_pic_boundaries_flag
+
+            for _ in 0..6 {
+                self.ue()?;
+            }
+        }
+
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Syntax, SyntaxInspector, VuiInfo};
+
+    /// Baseline 320x240 SPS, ending after `vui_parameters_present_flag`.
+    fn sps(vui: impl FnOnce(&mut BitWriter)) -> Vec<u8> {
+        let mut w = BitWriter::default();
+
+        w.write_bits(66, 8); // profile_idc
+        w.write_bits(0xc0, 8); // constraint flags
+        w.write_bits(30, 8); // level_idc
+        w.write_ue(0); // seq_parameter_set_id
+        w.write_ue(0); // log2_max_frame_num_minus4
+        w.write_ue(0); // pic_order_cnt_type
+        w.write_ue(2); // log2_max_pic_order_cnt_lsb_minus4
+        w.write_ue(1); // max_num_ref_frames
+        w.write_bit(false); // gaps_in_frame_num_value_allowed_flag
+        w.write_ue(19); // pic_width_in_mbs_minus1
+        w.write_ue(14); // pic_height_in_map_units_minus1
+        w.write_bit(true); // frame_mbs_only_flag
+        w.write_bit(true); // direct_8x8_inference_flag
+        w.write_bit(false); // frame_cropping_flag
+        vui(&mut w);
+
+        let mut out = vec![0x67];
+        out.extend(nal::from_rbsp(&w.finish()));
+        out
+    }
+
+    fn vui_of(sps: &[u8]) -> VuiInfo {
+        let data = [&[0, 0, 0, 1][..], sps].concat();
+
+        match SyntaxInspector::new().inspect(&data).remove(0).syntax {
+            Some(Syntax::Sps(info)) => {
+                assert_eq!((info.coded_width, info.coded_height), (320, 240));
+                info.vui.expect("no vui")
+            }
+            other => panic!("not an sps: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn with_pic_struct_adds_vui() {
+        let sps = with_pic_struct(&sps(|w| w.write_bit(false))).unwrap();
+        let vui = vui_of(&sps);
+
+        assert_eq!(vui.pic_struct_present, Some(true));
+        assert_eq!(vui.time_scale, None);
+    }
+
+    #[test]
+    fn with_pic_struct_keeps_timing_info() {
+        let sps = sps(|w| {
+            w.write_bit(true); // vui_parameters_present_flag
+            w.write_bits(0, 4); // aspect ratio, overscan, signal type, chroma location
+            w.write_bit(true); // timing_info_present_flag
+            w.write_bits(1, 32);
+            w.write_bits(60, 32);
+            w.write_bit(true); // fixed_frame_rate_flag
+            w.write_bits(0, 3); // hrd parameters, pic_struct_present_flag
+            w.write_bit(false); // bitstream_restriction_flag
+        });
+        let vui = vui_of(&with_pic_struct(&sps).unwrap());
+
+        assert_eq!(vui.pic_struct_present, Some(true));
+        assert_eq!((vui.num_units_in_tick, vui.time_scale), (Some(1), Some(60)));
+    }
+
+    #[test]
+    fn with_pic_struct_rejects_hrd() {
+        let sps = sps(|w| {
+            w.write_bit(true); // vui_parameters_present_flag
+            w.write_bits(0, 5); // aspect ratio, overscan, signal type, chroma location, timing
+            w.write_bit(true); // nal_hrd_parameters_present_flag
+        });
+
+        assert_eq!(with_pic_struct(&sps), None);
+        assert_eq!(with_pic_struct(&[0x67, 0x42]), None);
+    }
+
+    #[test]
+    fn timecode_from_timestamps() {
+        let source = TimecodeSource::Timestamps { timescale: 1000 };
+
+        assert_eq!(
+            source.timecode(3_723_500, 30.0),
+            Timecode {
+                hours: 1,
+                minutes: 2,
+                seconds: 3,
+                frames: 15,
+            },
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use flowly::Service;
+use futures::Stream;
+
+use crate::{DecodedFrame, Error, FrameBuffer};
+
+/// File format of a [`TimelineRecorder`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimelineFormat {
+    /// Comma separated values with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// Pass-through service placed after a decoder that appends one record per
+/// frame (timestamp, picture type, encoded size, keyframe and placeholder
+/// flags, decode time and dimensions) to a file while the pipeline runs, for
+/// plotting bitrate and latency of production runs.
+///
+/// The type is that of the first slice (`IDR`, `I`, `P`, `B`, `SP` or `SI`),
+/// empty or `null` for placeholders. The decode time is in microseconds. Frames are forwarded even if their
+/// record cannot be written, see [`write_errors`](Self::write_errors).
+pub struct TimelineRecorder {
+    writer: BufWriter<File>,
+    format: TimelineFormat,
+    write_errors: u64,
+}
+
+impl TimelineRecorder {
+    pub fn create(path: impl AsRef<Path>, format: TimelineFormat) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        if format == TimelineFormat::Csv {
+            writeln!(
+                writer,
+                "timestamp,type,size,keyframe,placeholder,decode_time_us,width,height"
+            )?;
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            write_errors: 0,
+        })
+    }
+
+    /// Number of records lost to I/O errors so far.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors
+    }
+
+    fn record<S, M: FrameBuffer>(&mut self, frame: &DecodedFrame<S, M>) -> std::io::Result<()> {
+        let kind = frame.picture_type.map(|kind| kind.as_str());
+        let decode_time = frame.decode_time.as_micros();
+        let w = &mut self.writer;
+
+        match self.format {
+            TimelineFormat::Csv => writeln!(
+                w,
+                "{},{},{},{},{},{decode_time},{},{}",
+                frame.timestamp,
+                kind.unwrap_or_default(),
+                frame.encoded_size,
+                frame.gop_start,
+                frame.synthetic,
+                frame.width,
+                frame.height
+            )?,
+            TimelineFormat::JsonLines => writeln!(
+                w,
+                r#"{{"timestamp":{},"type":{},"size":{},"keyframe":{},"placeholder":{},"decode_time_us":{decode_time},"width":{},"height":{}}}"#,
+                frame.timestamp,
+                kind.map_or("null".into(), |kind| format!(r#""{kind}""#)),
+                frame.encoded_size,
+                frame.gop_start,
+                frame.synthetic,
+                frame.width,
+                frame.height
+            )?,
+        }
+
+        // keep the records of a run that gets killed
+        w.flush()
+    }
+}
+
+impl<S: Send, M: FrameBuffer> Service<DecodedFrame<S, M>> for TimelineRecorder {
+    type Out = Result<DecodedFrame<S, M>, Error>;
+
+    fn handle(
+        &mut self,
+        frame: DecodedFrame<S, M>,
+        _cx: &flowly::Context,
+    ) -> impl Stream<Item = Self::Out> {
+        async_stream::stream! {
+            if let Err(err) = self.record(&frame) {
+                // only the first failure is logged, a full disk fails every record
+                if self.write_errors == 0 {
+                    log::error!("failed to write timeline record: {err}");
+                }
+
+                self.write_errors += 1;
+            }
+
+            yield Ok(frame);
+        }
+    }
+}
@@ -0,0 +1,921 @@
+use std::{
+    collections::{BTreeSet, BinaryHeap, VecDeque},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use flowly::{FrameFlags, spsc};
+use futures::executor::block_on;
+use openh264::{
+    decoder::{DecodedYUV, DecoderConfig, Flush},
+    formats::YUVSource,
+};
+
+use crate::{
+    BudgetPolicy, ChecksumAlgorithm, CodecConfig, ColorConvert, DecodeHook, DecodedFrame,
+    DecoderEvent, DecoderOptions, DecoderParts, Error, FrameAllocator, FrameBuffer, FrameChecksum,
+    Library, OutputFormat, PictureType,
+    buffer::{self, DefaultAllocator},
+    checksum, clock,
+    codec_config::ConfigTracker,
+    convert::YuvPlanes,
+    memory::MemoryCounters,
+    nal::{self, AccessUnitKind},
+    options::{ParseMode, Placeholder},
+    qos::QosMeter,
+    yuv,
+};
+
+pub(crate) type Input<S> = (Bytes, u64, S);
+pub(crate) type Output<S, M> = Result<DecodedFrame<S, M>, Error>;
+
+/// Where a [`Worker`] delivers its output.
+pub(crate) enum Sink<S, M> {
+    /// Channel read by the decoder while the worker runs on another thread.
+    Channel(spsc::Sender<Output<S, M>>),
+    /// Queue drained by the decoder itself when the worker runs inline.
+    Queue(VecDeque<Output<S, M>>),
+}
+
+/// Decoding state owned by the blocking worker of an [`Openh264Decoder`](crate::Openh264Decoder).
+pub(crate) struct Worker<S, M> {
+    decoder: openh264::decoder::Decoder,
+    /// Second instance fed the same input when verifying determinism.
+    shadow: Option<openh264::decoder::Decoder>,
+    options: DecoderOptions,
+    sink: Sink<S, M>,
+    counters: Arc<MemoryCounters>,
+    codec_config: Arc<Mutex<Option<CodecConfig>>>,
+    config_tracker: ConfigTracker,
+    /// Presentation timestamps of access units still inside the decoder,
+    /// popped smallest first as frames come out in presentation order.
+    ts_heap: BinaryHeap<Entry<S>>,
+    last_input: Option<u64>,
+    /// Timestamps of keyframe access units not yet out of the decoder.
+    gop_starts: BTreeSet<u64>,
+    waiting_for_keyframe: bool,
+    notified: bool,
+    last_frame: Option<LastFrame>,
+    /// Recent access units kept for error dumps.
+    history: VecDeque<(u64, Bytes)>,
+    parameter_sets: nal::ParameterSets,
+    hook: Option<Box<dyn DecodeHook<S, M>>>,
+    allocator: Box<dyn FrameAllocator<M>>,
+    /// Access units before the in point, from the last keyframe on.
+    lead_in: Vec<(Input<S>, AccessUnitKind)>,
+    past_out_point: bool,
+    /// Whether a keyframe was requested since the last one decoded.
+    keyframe_requested: bool,
+    qos: Option<QosMeter>,
+    /// Output held back while the consumer is behind, on pool workers only,
+    /// which must not block a shared thread on the channel of one stream.
+    held: Option<VecDeque<Output<S, M>>>,
+    finished: bool,
+}
+
+/// Last successfully decoded picture, kept around for placeholder synthesis.
+struct LastFrame {
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+    chroma: Option<[Vec<u8>; 2]>,
+    strides: (usize, usize),
+    checksum: Option<FrameChecksum>,
+}
+
+impl<S: Default, M: FrameBuffer> Worker<S, M> {
+    pub(crate) fn new(
+        options: DecoderOptions,
+        sink: Sink<S, M>,
+        counters: Arc<MemoryCounters>,
+        codec_config: Arc<Mutex<Option<CodecConfig>>>,
+        parts: DecoderParts<S, M>,
+    ) -> Result<Self, Error> {
+        let decoder = create_decoder(&options.library)?;
+        let shadow = options
+            .verify_determinism
+            .then(|| create_decoder(&options.library))
+            .transpose()?;
+
+        let mut last_frame = None;
+
+        if let Some((width, height)) = options.expected_resolution {
+            counters.set_dimensions(width, height);
+
+            if options.placeholder == Some(Placeholder::RepeatLast) {
+                last_frame = Some(LastFrame::with_capacity(
+                    width as usize,
+                    height as usize,
+                    options.output_format,
+                ));
+            }
+        }
+
+        let qos = options.qos.map(QosMeter::new);
+
+        Ok(Self {
+            decoder,
+            shadow,
+            waiting_for_keyframe: options.keyframe_gating,
+            options,
+            sink,
+            counters,
+            codec_config,
+            config_tracker: ConfigTracker::default(),
+            ts_heap: BinaryHeap::new(),
+            last_input: None,
+            gop_starts: BTreeSet::new(),
+            notified: false,
+            last_frame,
+            history: VecDeque::new(),
+            parameter_sets: nal::ParameterSets::default(),
+            hook: parts.hook,
+            allocator: parts
+                .allocator
+                .unwrap_or_else(|| Box::new(DefaultAllocator)),
+            lead_in: Vec::new(),
+            past_out_point: false,
+            keyframe_requested: false,
+            qos,
+            held: None,
+            finished: false,
+        })
+    }
+
+    pub(crate) fn run(mut self, mut rx: spsc::Receiver<Input<S>>) -> Result<(), Error> {
+        while let Some(input) = block_on(rx.recv()) {
+            if !self.receive(input) {
+                break;
+            }
+        }
+
+        self.finish();
+
+        Ok(())
+    }
+
+    /// Takes one access unit off the input queue, returns `false` once the output side is gone.
+    pub(crate) fn receive(&mut self, input: Input<S>) -> bool {
+        self.counters
+            .input_queue
+            .fetch_sub(input.0.len(), Ordering::Relaxed);
+
+        let alive = self.process(input);
+
+        if let Some(qos) = &mut self.qos {
+            qos.poll(&self.counters);
+        }
+
+        alive
+    }
+
+    fn process(&mut self, (data, timestamp, source): Input<S>) -> bool {
+        let data = match &mut self.hook {
+            Some(hook) => match hook.before_decode(data, timestamp) {
+                Some(data) => data,
+                None => return true,
+            },
+            None => data,
+        };
+
+        let data = match self.options.parse_mode {
+            ParseMode::Strict => match nal::validate(&data) {
+                Ok(()) => data,
+                Err(reason) => return self.send(Err(Error::Malformed(timestamp, reason))),
+            },
+            ParseMode::Permissive => match nal::resync(&data) {
+                Some(clean) if clean.is_empty() => {
+                    log::debug!("dropping access unit {timestamp}, no well-formed NAL units");
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                Some(clean) => clean,
+                None => data,
+            },
+        };
+
+        let kind = nal::classify(&data);
+
+        if self.options.log_nal_units {
+            log::info!(
+                "access unit {timestamp}, {} bytes, {kind:?}: {}",
+                data.len(),
+                nal::describe(&data)
+            );
+        }
+
+        match self.options.time_range {
+            Some(_) => self.limit_to_range((data, timestamp, source), kind),
+            None => self.decode_unit((data, timestamp, source), kind),
+        }
+    }
+
+    /// Holds back access units before the in point from the last keyframe on,
+    /// decoding them once the in point is reached, and drops all input after
+    /// the first keyframe past the out point.
+    fn limit_to_range(&mut self, input: Input<S>, kind: AccessUnitKind) -> bool {
+        let Some(range) = self.options.time_range.clone() else {
+            return self.decode_unit(input, kind);
+        };
+
+        let timestamp = input.1;
+
+        if self.past_out_point {
+            return true;
+        }
+
+        if kind == AccessUnitKind::Keyframe && timestamp > *range.end() {
+            log::debug!("out point {} passed at {timestamp}", range.end());
+            self.past_out_point = true;
+
+            return true;
+        }
+
+        if timestamp < *range.start() {
+            if kind == AccessUnitKind::Keyframe {
+                // parameter sets sent ahead of the keyframe belong to its GOP
+                let gop = self
+                    .lead_in
+                    .iter()
+                    .rposition(|(_, kind)| *kind != AccessUnitKind::NonVcl)
+                    .map_or(0, |pos| pos + 1);
+
+                self.lead_in.drain(..gop);
+            }
+
+            self.lead_in.push((input, kind));
+
+            return true;
+        }
+
+        for (input, kind) in std::mem::take(&mut self.lead_in) {
+            if !self.decode_unit(input, kind) {
+                return false;
+            }
+        }
+
+        self.decode_unit(input, kind)
+    }
+
+    fn decode_unit(&mut self, (data, timestamp, source): Input<S>, kind: AccessUnitKind) -> bool {
+        if self.waiting_for_keyframe {
+            match kind {
+                AccessUnitKind::Keyframe => self.waiting_for_keyframe = false,
+                AccessUnitKind::Inter => {
+                    log::debug!("dropping inter frame {timestamp} until keyframe");
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+
+                    if self.options.notify_waiting_for_keyframe && !self.notified {
+                        self.notified = true;
+                        self.notify(DecoderEvent::WaitingForKeyframe);
+                    }
+
+                    return true;
+                }
+                AccessUnitKind::NonVcl => (),
+            }
+        }
+
+        if let Some(config) = self.config_tracker.update(&data) {
+            *self.codec_config.lock().unwrap() = Some(config);
+        }
+
+        let data = match self.options.nal_filter.apply(&data) {
+            Some(filtered) if filtered.is_empty() => return true,
+            Some(filtered) => filtered,
+            None => data,
+        };
+
+        let data = match self
+            .options
+            .dedup_parameter_sets
+            .then(|| self.parameter_sets.dedup(&data))
+            .flatten()
+        {
+            Some(deduped) if deduped.is_empty() => return true,
+            Some(deduped) => deduped,
+            None => data,
+        };
+
+        if let Some((depth, _)) = &self.options.error_capture {
+            if self.history.len() >= *depth {
+                self.history.pop_front();
+            }
+
+            self.history.push_back((timestamp, data.clone()));
+        }
+
+        // only units reaching the decoder come out again and take their entry along
+        if kind == AccessUnitKind::Keyframe {
+            self.gop_starts.insert(timestamp);
+        }
+
+        // chunks of one access unit share its timestamp, it must enter the heap once
+        if self.last_input != Some(timestamp) {
+            self.last_input = Some(timestamp);
+            let picture_type = PictureType::of_access_unit(&data);
+            self.ts_heap
+                .push(Entry(timestamp, source, data.len(), picture_type));
+        } else {
+            let mut entries = std::mem::take(&mut self.ts_heap).into_vec();
+
+            if let Some(entry) = entries.iter_mut().find(|entry| entry.0 == timestamp) {
+                entry.2 += data.len();
+                entry.3 = entry.3.or_else(|| PictureType::of_access_unit(&data));
+            }
+
+            self.ts_heap = entries.into();
+        }
+
+        let format = self.options.output_format;
+        let converter = self.options.color_converter.clone();
+        let started = clock::now();
+        let decoded = self.decoder.decode(&data);
+        let decode_time = clock::elapsed(started);
+        let verified = self
+            .shadow
+            .as_mut()
+            .is_none_or(|shadow| agree(&decoded, &shadow.decode(&data)));
+
+        let res = match decoded {
+            Ok(Some(frame)) => Ok(Some(make_frame(
+                self.ts_heap.pop(),
+                frame,
+                format,
+                &*converter,
+                &*self.allocator,
+                self.options.checksum,
+            ))),
+            Ok(None) => Ok(None),
+            Err(err) => Err(Error::from(err)),
+        };
+
+        let alive = match res {
+            Ok(Some(mut frame)) => {
+                frame.gop_start = self.gop_starts.remove(&frame.timestamp);
+                frame.decode_time = decode_time;
+                self.keyframe_requested &= !frame.gop_start;
+
+                self.remember(&frame);
+                self.send(Ok(frame))
+            }
+            Ok(None) => true,
+            Err(err) => {
+                self.dump_history(timestamp);
+                self.parameter_sets.clear();
+
+                if self.options.parse_mode == ParseMode::Permissive && self.options.keyframe_gating
+                {
+                    log::debug!("resynchronizing on the next keyframe after {timestamp}");
+                    self.waiting_for_keyframe = true;
+                }
+
+                if self.options.request_keyframes && !self.keyframe_requested {
+                    self.keyframe_requested = true;
+                    self.notify(DecoderEvent::KeyframeRequested(timestamp));
+                }
+
+                match self.placeholder() {
+                    Some(frame) => {
+                        log::warn!(
+                            "replacing undecodable frame {} with placeholder: {err}",
+                            frame.timestamp
+                        );
+                        self.send(Ok(frame))
+                    }
+                    None => {
+                        // the access unit will never come out, keep its timestamp
+                        // from shifting onto the frames after it
+                        self.ts_heap.retain(|entry| entry.0 != timestamp);
+                        self.gop_starts.remove(&timestamp);
+                        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                        self.send(Err(err))
+                    }
+                }
+            }
+        };
+
+        if !verified {
+            log::error!(
+                "openh264 output for access unit {timestamp} differs between decode passes"
+            );
+
+            self.notify(DecoderEvent::Nondeterministic(timestamp));
+        }
+
+        alive
+    }
+
+    /// Flushes the frames still inside the decoder at the end of the stream.
+    pub(crate) fn finish(&mut self) {
+        self.finished = true;
+
+        // input held back for an in point that never came
+        for (input, kind) in std::mem::take(&mut self.lead_in) {
+            if !self.decode_unit(input, kind) {
+                break;
+            }
+        }
+
+        let format = self.options.output_format;
+        let converter = self.options.color_converter.clone();
+        let shadow = self.shadow.as_mut().map(|shadow| shadow.flush_remaining());
+
+        let (frames, verified) = match self.decoder.flush_remaining() {
+            Ok(remaining) => {
+                let verified = shadow.is_none_or(|other| {
+                    other.is_ok_and(|other| {
+                        other.len() == remaining.len()
+                            && remaining
+                                .iter()
+                                .zip(&other)
+                                .all(|(a, b)| same_picture(a, b))
+                    })
+                });
+
+                // frames borrow the decoder, convert them all before sending
+                let frames: Vec<_> = remaining
+                    .into_iter()
+                    .map(|frame| {
+                        make_frame(
+                            self.ts_heap.pop(),
+                            frame,
+                            format,
+                            &*converter,
+                            &*self.allocator,
+                            self.options.checksum,
+                        )
+                    })
+                    .collect();
+
+                (frames, verified)
+            }
+            Err(err) => {
+                log::error!("openh264::Decoder::flush_remaining error: {err}");
+                (Vec::new(), true)
+            }
+        };
+
+        for mut frame in frames {
+            frame.gop_start = self.gop_starts.remove(&frame.timestamp);
+
+            if !self.send(Ok(frame)) {
+                return;
+            }
+        }
+
+        if !verified {
+            let timestamp = self.last_input.unwrap_or_default();
+
+            log::error!("openh264 output flushed at end of stream differs between decode passes");
+            self.notify(DecoderEvent::Nondeterministic(timestamp));
+        }
+    }
+
+    fn notify(&mut self, event: DecoderEvent) {
+        match &mut self.hook {
+            Some(hook) => hook.on_event(event),
+            None => log::debug!("{event:?} without a hook to report it to"),
+        }
+    }
+
+    /// Writes the captured access units to a file, if capturing is enabled.
+    fn dump_history(&mut self, timestamp: u64) {
+        let Some((_, dir)) = &self.options.error_capture else {
+            return;
+        };
+
+        let path = dir.join(format!("openh264-error-{timestamp}.h264"));
+        let config = self.codec_config.lock().unwrap().clone();
+
+        match write_dump(&path, config.as_ref(), &self.history) {
+            Ok(()) => log::warn!(
+                "dumped {} access units before decode error to {}",
+                self.history.len(),
+                path.display()
+            ),
+            Err(err) => log::error!("failed to write {}: {err}", path.display()),
+        }
+
+        self.history.clear();
+    }
+
+    #[inline]
+    fn send(&mut self, mut res: Output<S, M>) -> bool {
+        // lead-in frames are only decoded as references
+        if let (Ok(frame), Some(range)) = (&res, &self.options.time_range) {
+            if !range.contains(&frame.timestamp) {
+                return true;
+            }
+        }
+
+        if let (Ok(frame), Some(hook)) = (&mut res, &mut self.hook) {
+            if !hook.after_decode(frame) {
+                return true;
+            }
+        }
+
+        if let (Ok(frame), Some((budget, policy))) = (&mut res, &self.options.memory_budget) {
+            // only a worker with a thread of its own may wait for other frames
+            // to be released, inline and pool workers would stall everyone else
+            let policy = match self.sink {
+                Sink::Channel(_) if self.held.is_none() => *policy,
+                _ => BudgetPolicy::Drop,
+            };
+
+            match budget.reserve(frame.size(), policy) {
+                Some(reservation) => frame.reservation = Some(Arc::new(reservation)),
+                None => {
+                    log::debug!(
+                        "memory budget exhausted, dropping frame {}",
+                        frame.timestamp
+                    );
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+
+                    return true;
+                }
+            }
+        }
+
+        if let (Ok(frame), Some(qos)) = (&res, &mut self.qos) {
+            qos.record(frame.synthetic);
+        }
+
+        let size = res.as_ref().map(|frame| frame.size()).unwrap_or_default();
+
+        self.counters
+            .output_queue
+            .fetch_add(size, Ordering::Relaxed);
+
+        let full = self.output_full();
+
+        if let Some(held) = &mut self.held {
+            if full || !held.is_empty() {
+                held.push_back(res);
+                return true;
+            }
+        }
+
+        self.deliver(res)
+    }
+
+    fn deliver(&mut self, res: Output<S, M>) -> bool {
+        let size = res.as_ref().map(|frame| frame.size()).unwrap_or_default();
+
+        self.counters.output_items.fetch_add(1, Ordering::Relaxed);
+
+        let sent = match &mut self.sink {
+            Sink::Channel(tx) => block_on(tx.send(res)).is_ok(),
+            Sink::Queue(queue) => {
+                queue.push_back(res);
+                true
+            }
+        };
+
+        if !sent {
+            self.counters.output_items.fetch_sub(1, Ordering::Relaxed);
+            self.counters
+                .output_queue
+                .fetch_sub(size, Ordering::Relaxed);
+        }
+
+        sent
+    }
+
+    #[inline]
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Holds output back instead of waiting for room in the channel.
+    pub(crate) fn hold_output(&mut self) {
+        self.held = Some(VecDeque::new());
+    }
+
+    /// Whether the channel to the consumer is full, so a send would wait.
+    pub(crate) fn output_full(&self) -> bool {
+        self.counters.output_items.load(Ordering::Relaxed) >= self.options.decode_ahead.max(1)
+    }
+
+    /// Sends held output while there is room, returns whether none is left.
+    pub(crate) fn release_output(&mut self) -> bool {
+        while !self.output_full() {
+            let Some(res) = self.held.as_mut().and_then(VecDeque::pop_front) else {
+                return true;
+            };
+
+            if !self.deliver(res) {
+                // nobody left to deliver to
+                self.held = Some(VecDeque::new());
+                return true;
+            }
+        }
+
+        self.held.as_ref().is_none_or(VecDeque::is_empty)
+    }
+
+    /// Takes the oldest output of an inline worker.
+    #[inline]
+    pub(crate) fn pop_output(&mut self) -> Option<Output<S, M>> {
+        match &mut self.sink {
+            Sink::Queue(queue) => queue.pop_front(),
+            Sink::Channel(_) => None,
+        }
+    }
+
+    fn remember(&mut self, frame: &DecodedFrame<S, M>) {
+        self.counters.set_dimensions(frame.width, frame.height);
+
+        let Some(placeholder) = self.options.placeholder else {
+            return;
+        };
+
+        let last = self.last_frame.get_or_insert_with(|| LastFrame {
+            width: 0,
+            height: 0,
+            data: Vec::new(),
+            chroma: None,
+            strides: (0, 0),
+            checksum: None,
+        });
+
+        last.width = frame.width;
+        last.height = frame.height;
+
+        if placeholder == Placeholder::RepeatLast {
+            last.data.clear();
+            last.data.extend_from_slice(frame.data.as_slice());
+
+            match (&mut last.chroma, &frame.chroma) {
+                (Some([last_u, last_v]), Some([u, v])) => {
+                    last_u.clear();
+                    last_u.extend_from_slice(u.as_slice());
+                    last_v.clear();
+                    last_v.extend_from_slice(v.as_slice());
+                }
+                (last_chroma, chroma) => {
+                    *last_chroma = chroma
+                        .as_ref()
+                        .map(|[u, v]| [u.as_slice().to_vec(), v.as_slice().to_vec()]);
+                }
+            }
+
+            last.strides = frame.strides;
+            last.checksum = frame.checksum;
+
+            let retained = last.data.capacity()
+                + last
+                    .chroma
+                    .iter()
+                    .flatten()
+                    .map(Vec::capacity)
+                    .sum::<usize>();
+
+            self.counters.retained.store(retained, Ordering::Relaxed);
+        }
+    }
+
+    fn placeholder(&mut self) -> Option<DecodedFrame<S, M>> {
+        // buffers allocated up front hold no picture yet
+        let last = self.last_frame.as_ref().filter(|last| last.width > 0)?;
+
+        let (width, height) = (last.width, last.height);
+        let (w, h) = (width as usize, height as usize);
+
+        let allocator = &*self.allocator;
+        let placeholder = self.options.placeholder?;
+
+        // hashed over I420 planes whatever the output format, like decoded frames
+        let checksum = match placeholder {
+            Placeholder::RepeatLast => last.checksum,
+            Placeholder::SolidColor(rgb) => self
+                .options
+                .checksum
+                .map(|algorithm| checksum::compute_solid((w, h), yuv::color(rgb), algorithm)),
+        };
+
+        let (data, chroma, strides) = match (placeholder, self.options.output_format) {
+            (Placeholder::RepeatLast, _) => (
+                buffer::copy_to(allocator, &last.data),
+                last.chroma
+                    .as_ref()
+                    .map(|[u, v]| [buffer::copy_to(allocator, u), buffer::copy_to(allocator, v)]),
+                last.strides,
+            ),
+            (Placeholder::SolidColor(rgb), OutputFormat::Rgb8) => (
+                buffer::copy_to(allocator, &rgb.repeat(w * h)),
+                None,
+                (w * 3, 0),
+            ),
+            (Placeholder::SolidColor(rgb), OutputFormat::I420) => {
+                let (cw, ch) = yuv::chroma_dimensions(w, h);
+                let (y, u, v) = yuv::color(rgb);
+
+                (
+                    filled(allocator, w * h, y),
+                    Some([filled(allocator, cw * ch, u), filled(allocator, cw * ch, v)]),
+                    (w, cw),
+                )
+            }
+        };
+
+        let in_frame = self.ts_heap.pop();
+
+        Some(DecodedFrame {
+            timestamp: in_frame.as_ref().map(|x| x.0).unwrap_or_default(),
+            encoded_size: in_frame.as_ref().map(|x| x.2).unwrap_or_default(),
+            data,
+            chroma,
+            strides,
+            width,
+            height,
+            source: in_frame.map(|x| x.1).unwrap_or_default(),
+            flags: FrameFlags::VIDEO_STREAM,
+            synthetic: true,
+            gop_start: false,
+            picture_type: None,
+            checksum,
+            decode_time: Duration::ZERO,
+            reservation: None,
+        })
+    }
+}
+
+fn write_dump(
+    path: &Path,
+    config: Option<&CodecConfig>,
+    history: &VecDeque<(u64, Bytes)>,
+) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    for unit in config
+        .iter()
+        .flat_map(|config| config.sps.iter().chain(&config.pps))
+    {
+        file.write_all(&[0, 0, 0, 1])?;
+        file.write_all(unit)?;
+    }
+
+    for (_, data) in history {
+        file.write_all(data)?;
+    }
+
+    file.flush()
+}
+
+pub(crate) fn create_decoder(library: &Library) -> Result<openh264::decoder::Decoder, Error> {
+    let decode_config = DecoderConfig::new().flush_after_decode(Flush::NoFlush);
+
+    Ok(openh264::decoder::Decoder::with_api_config(
+        library.load()?,
+        decode_config,
+    )?)
+}
+
+type Decoded<'a> = Result<Option<DecodedYUV<'a>>, openh264::Error>;
+
+/// Whether two decode passes over the same access unit came to the same result.
+fn agree(a: &Decoded<'_>, b: &Decoded<'_>) -> bool {
+    match (a, b) {
+        (Ok(Some(a)), Ok(Some(b))) => same_picture(a, b),
+        (Ok(None), Ok(None)) | (Err(_), Err(_)) => true,
+        _ => false,
+    }
+}
+
+/// Bit-exact comparison of the visible part of two pictures.
+fn same_picture(a: &DecodedYUV<'_>, b: &DecodedYUV<'_>) -> bool {
+    let (width, height) = a.dimensions();
+
+    if b.dimensions() != (width, height) {
+        return false;
+    }
+
+    let (cw, ch) = yuv::chroma_dimensions(width, height);
+    let (sa, sb) = (a.strides(), b.strides());
+
+    let rows_equal = |pa: &[u8], pb: &[u8], stride_a: usize, stride_b: usize, row: usize, rows| {
+        (0..rows).all(|r| pa[r * stride_a..][..row] == pb[r * stride_b..][..row])
+    };
+
+    rows_equal(a.y(), b.y(), sa.0, sb.0, width, height)
+        && rows_equal(a.u(), b.u(), sa.1, sb.1, cw, ch)
+        && rows_equal(a.v(), b.v(), sa.2, sb.2, cw, ch)
+}
+
+fn make_frame<S: Default, M: FrameBuffer>(
+    in_frame: Option<Entry<S>>,
+    frame: DecodedYUV<'_>,
+    format: OutputFormat,
+    converter: &dyn ColorConvert,
+    allocator: &dyn FrameAllocator<M>,
+    checksum: Option<ChecksumAlgorithm>,
+) -> DecodedFrame<S, M> {
+    let dims = frame.dimensions();
+    let checksum = checksum.map(|algorithm| checksum::compute_decoded(&frame, algorithm));
+
+    let (data, chroma, strides) = match format {
+        OutputFormat::Rgb8 => {
+            let mut data = allocator.alloc(dims.0 * dims.1 * 3);
+
+            converter.i420_to_rgb8(&YuvPlanes::from_decoded(&frame), data.as_mut_slice());
+
+            (data, None, (dims.0 * 3, 0))
+        }
+        OutputFormat::I420 => {
+            // openh264 lays out U and V with the same stride
+            let (y_stride, uv_stride, _) = frame.strides();
+            let chroma = [
+                buffer::copy_to(allocator, frame.u()),
+                buffer::copy_to(allocator, frame.v()),
+            ];
+
+            (
+                buffer::copy_to(allocator, frame.y()),
+                Some(chroma),
+                (y_stride, uv_stride),
+            )
+        }
+    };
+
+    DecodedFrame {
+        timestamp: in_frame.as_ref().map(|x| x.0).unwrap_or_default(),
+        encoded_size: in_frame.as_ref().map(|x| x.2).unwrap_or_default(),
+        picture_type: in_frame.as_ref().and_then(|x| x.3),
+        data,
+        chroma,
+        strides,
+        width: dims.0 as _,
+        height: dims.1 as _,
+        source: in_frame.map(|x| x.1).unwrap_or_default(),
+        flags: FrameFlags::VIDEO_STREAM,
+        synthetic: false,
+        gop_start: false,
+        checksum,
+        decode_time: Duration::ZERO,
+        reservation: None,
+    }
+}
+
+fn filled<M: FrameBuffer>(allocator: &dyn FrameAllocator<M>, len: usize, value: u8) -> M {
+    let mut block = allocator.alloc(len);
+    block.as_mut_slice().fill(value);
+    block
+}
+
+impl LastFrame {
+    /// Empty buffers sized for pictures of the given resolution.
+    fn with_capacity(width: usize, height: usize, format: OutputFormat) -> Self {
+        let (cw, ch) = yuv::chroma_dimensions(width, height);
+
+        let (data, chroma) = match format {
+            OutputFormat::Rgb8 => (Vec::with_capacity(width * height * 3), None),
+            OutputFormat::I420 => (
+                Vec::with_capacity(width * height),
+                Some([Vec::with_capacity(cw * ch), Vec::with_capacity(cw * ch)]),
+            ),
+        };
+
+        Self {
+            width: 0,
+            height: 0,
+            data,
+            chroma,
+            strides: (0, 0),
+        }
+    }
+}
+
+/// Timestamp, source, size and picture type of an access unit inside the decoder.
+struct Entry<S>(u64, S, usize, Option<PictureType>);
+
+impl<S> std::ops::Deref for Entry<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.1
+    }
+}
+
+impl<S> PartialEq for Entry<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<S> Eq for Entry<S> {}
+
+impl<S> PartialOrd for Entry<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(Self::cmp(self, other))
+    }
+}
+
+impl<S> Ord for Entry<S> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.0.cmp(&self.0)
+    }
+}
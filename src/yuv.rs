@@ -0,0 +1,422 @@
+use bytes::Bytes;
+use openh264::formats::YUVSource;
+
+/// 8-bit I420 picture whose planes may carry row padding.
+#[derive(Debug, Clone)]
+pub(crate) struct Yuv420 {
+    width: usize,
+    height: usize,
+    strides: (usize, usize, usize),
+    y: Bytes,
+    u: Bytes,
+    v: Bytes,
+}
+
+impl Yuv420 {
+    /// Converts packed RGB888 rows `stride` bytes apart, see [`luma`] and
+    /// [`chroma`], writing into the planes of `scratch` unless they are still
+    /// referenced elsewhere.
+    pub(crate) fn from_rgb8_reusing(
+        rgb: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        scratch: Option<Self>,
+    ) -> Option<Self> {
+        if stride < width * 3 || !fits(rgb.len(), width * 3, height, stride) {
+            return None;
+        }
+
+        let (cw, ch) = chroma_dimensions(width, height);
+        let (mut y, mut u, mut v) = scratch.map(Self::into_buffers).unwrap_or_default();
+
+        y.resize(width * height, 0);
+        u.resize(cw * ch, 0);
+        v.resize(cw * ch, 0);
+
+        for row in 0..height {
+            for col in 0..width {
+                let px = &rgb[row * stride + col * 3..][..3];
+
+                y[row * width + col] = luma(px[0] as i32, px[1] as i32, px[2] as i32);
+            }
+        }
+
+        for row in 0..ch {
+            for col in 0..cw {
+                let (mut r, mut g, mut b, mut n) = (0, 0, 0, 0);
+
+                for sy in row * 2..(row * 2 + 2).min(height) {
+                    for sx in col * 2..(col * 2 + 2).min(width) {
+                        let px = &rgb[sy * stride + sx * 3..][..3];
+
+                        r += px[0] as i32;
+                        g += px[1] as i32;
+                        b += px[2] as i32;
+                        n += 1;
+                    }
+                }
+
+                (u[row * cw + col], v[row * cw + col]) = chroma(r / n, g / n, b / n);
+            }
+        }
+
+        Some(Self::packed(width, height, y, u, v))
+    }
+
+    /// Wraps existing planes without copying, checking they cover the picture.
+    pub(crate) fn from_planes(
+        width: usize,
+        height: usize,
+        strides: (usize, usize, usize),
+        (y, u, v): (Bytes, Bytes, Bytes),
+    ) -> Option<Self> {
+        let (cw, ch) = chroma_dimensions(width, height);
+
+        let valid = strides.0 >= width
+            && strides.1 >= cw
+            && strides.2 >= cw
+            && fits(y.len(), width, height, strides.0)
+            && fits(u.len(), cw, ch, strides.1)
+            && fits(v.len(), cw, ch, strides.2);
+
+        valid.then_some(Self {
+            width,
+            height,
+            strides,
+            y,
+            u,
+            v,
+        })
+    }
+
+    /// Builds a picture from planes with 4:2:2 or 4:4:4 chroma, box filtering
+    /// chroma down to 4:2:0 (an average over the `sub_x` by `sub_y`
+    /// complement, i.e. row pairs for 4:2:2 and 2x2 blocks for 4:4:4).
+    /// Luma is used in place.
+    pub(crate) fn from_subsampled(
+        width: usize,
+        height: usize,
+        strides: (usize, usize, usize),
+        (y, u, v): (Bytes, Bytes, Bytes),
+        (sub_x, sub_y): (usize, usize),
+    ) -> Option<Self> {
+        let (scw, sch) = (width.div_ceil(sub_x), height.div_ceil(sub_y));
+
+        let valid = strides.0 >= width
+            && strides.1 >= scw
+            && strides.2 >= scw
+            && fits(y.len(), width, height, strides.0)
+            && fits(u.len(), scw, sch, strides.1)
+            && fits(v.len(), scw, sch, strides.2);
+
+        if !valid {
+            return None;
+        }
+
+        let (cw, ch) = chroma_dimensions(width, height);
+        let factors = (2 / sub_x, 2 / sub_y);
+
+        Some(Self {
+            width,
+            height,
+            strides: (strides.0, cw, cw),
+            y,
+            u: downsample_plane(&u, (scw, sch, strides.1), factors, (cw, ch)).into(),
+            v: downsample_plane(&v, (scw, sch, strides.2), factors, (cw, ch)).into(),
+        })
+    }
+
+    /// An all-zero picture, e.g. to allocate conversion buffers up front.
+    pub(crate) fn blank(width: usize, height: usize) -> Self {
+        let (cw, ch) = chroma_dimensions(width, height);
+
+        Self::packed(
+            width,
+            height,
+            vec![0; width * height],
+            vec![0; cw * ch],
+            vec![0; cw * ch],
+        )
+    }
+
+    /// Takes back the plane buffers, empty where they are shared.
+    fn into_buffers(self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let reclaim = |plane: Bytes| plane.try_into_mut().map(Vec::from).unwrap_or_default();
+
+        (reclaim(self.y), reclaim(self.u), reclaim(self.v))
+    }
+
+    fn packed(width: usize, height: usize, y: Vec<u8>, u: Vec<u8>, v: Vec<u8>) -> Self {
+        let (cw, _) = chroma_dimensions(width, height);
+
+        Self {
+            width,
+            height,
+            strides: (width, cw, cw),
+            y: y.into(),
+            u: u.into(),
+            v: v.into(),
+        }
+    }
+
+    /// Restricts the picture to a rectangle without copying, aligned as
+    /// described in [`crop_rect`].
+    pub(crate) fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Self> {
+        let (x, y, width, height) = crop_rect((x, y, width, height), (self.width, self.height))?;
+
+        let chroma_offset = |stride: usize| (y / 2) * stride + x / 2;
+
+        Some(Self {
+            width,
+            height,
+            strides: self.strides,
+            y: self.y.slice(y * self.strides.0 + x..),
+            u: self.u.slice(chroma_offset(self.strides.1)..),
+            v: self.v.slice(chroma_offset(self.strides.2)..),
+        })
+    }
+
+    /// Bilinear resize of all three planes into a tightly packed picture.
+    pub(crate) fn scale(&self, width: usize, height: usize) -> Self {
+        if (width, height) == (self.width, self.height) {
+            return self.clone();
+        }
+
+        let (scw, sch) = chroma_dimensions(self.width, self.height);
+        let (dcw, dch) = chroma_dimensions(width, height);
+
+        Self::packed(
+            width,
+            height,
+            scale_plane(
+                &self.y,
+                (self.width, self.height, self.strides.0),
+                width,
+                height,
+            ),
+            scale_plane(&self.u, (scw, sch, self.strides.1), dcw, dch),
+            scale_plane(&self.v, (scw, sch, self.strides.2), dcw, dch),
+        )
+    }
+
+    #[inline]
+    pub(crate) fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+impl YUVSource for Yuv420 {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn strides(&self) -> (usize, usize, usize) {
+        self.strides
+    }
+
+    fn y(&self) -> &[u8] {
+        &self.y
+    }
+
+    fn u(&self) -> &[u8] {
+        &self.u
+    }
+
+    fn v(&self) -> &[u8] {
+        &self.v
+    }
+}
+
+/// BT.601 full range luma of an RGB color, matching what
+/// [`Bt601FullRange`](crate::Bt601FullRange) converts decoded pictures back with.
+#[inline]
+pub(crate) fn luma(r: i32, g: i32, b: i32) -> u8 {
+    ((77 * r + 150 * g + 29 * b + 128) >> 8) as u8
+}
+
+/// BT.601 full range chroma of an RGB color, see [`luma`].
+#[inline]
+pub(crate) fn chroma(r: i32, g: i32, b: i32) -> (u8, u8) {
+    let clamp = |value: i32| (value + 128).clamp(0, 255) as u8;
+
+    (
+        clamp((-43 * r - 85 * g + 128 * b + 128) >> 8),
+        clamp((128 * r - 107 * g - 21 * b + 128) >> 8),
+    )
+}
+
+/// Y, U and V of an RGB color, see [`luma`] and [`chroma`].
+#[inline]
+pub(crate) fn color([r, g, b]: [u8; 3]) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let (u, v) = chroma(r, g, b);
+
+    (luma(r, g, b), u, v)
+}
+
+/// Widens `rect` to even coordinates on all sides, so every chroma sample
+/// covers the same 2x2 luma block as in the full picture, and clips it to an
+/// even part of a `width` x `height` picture. `None` if nothing is left.
+#[inline]
+pub(crate) fn crop_rect(
+    (x, y, width, height): (usize, usize, usize, usize),
+    (frame_width, frame_height): (usize, usize),
+) -> Option<(usize, usize, usize, usize)> {
+    let (left, top) = (x & !1, y & !1);
+    let right = ((x + width + 1) & !1).min(frame_width & !1);
+    let bottom = ((y + height + 1) & !1).min(frame_height & !1);
+
+    (right > left && bottom > top).then_some((left, top, right - left, bottom - top))
+}
+
+pub(crate) fn chroma_dimensions(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(2), height.div_ceil(2))
+}
+
+/// Whether `len` bytes hold `rows` rows of `row` bytes placed `stride` bytes apart.
+#[inline]
+fn fits(len: usize, row: usize, rows: usize, stride: usize) -> bool {
+    rows == 0 || len >= stride * (rows - 1) + row
+}
+
+fn scale_plane(
+    src: &[u8],
+    (sw, sh, stride): (usize, usize, usize),
+    dw: usize,
+    dh: usize,
+) -> Vec<u8> {
+    let mut dst = vec![0; dw * dh];
+
+    if sw == 0 || sh == 0 {
+        return dst;
+    }
+
+    // 16.16 fixed point steps, sampling at pixel centers
+    let step_x = (sw << 16) / dw.max(1);
+    let step_y = (sh << 16) / dh.max(1);
+
+    for row in 0..dh {
+        let fy = (row * step_y + step_y / 2).saturating_sub(1 << 15);
+        let y0 = (fy >> 16).min(sh - 1);
+        let y1 = (y0 + 1).min(sh - 1);
+        let wy = (fy & 0xffff) as u32;
+
+        for col in 0..dw {
+            let fx = (col * step_x + step_x / 2).saturating_sub(1 << 15);
+            let x0 = (fx >> 16).min(sw - 1);
+            let x1 = (x0 + 1).min(sw - 1);
+            let wx = (fx & 0xffff) as u32;
+
+            let top = lerp(src[y0 * stride + x0], src[y0 * stride + x1], wx);
+            let bottom = lerp(src[y1 * stride + x0], src[y1 * stride + x1], wx);
+
+            dst[row * dw + col] =
+                ((top * (0x10000 - wy) as u64 + bottom * wy as u64 + (1 << 31)) >> 32) as u8;
+        }
+    }
+
+    dst
+}
+
+/// Averages `fx` by `fy` blocks of the source plane into a packed plane.
+fn downsample_plane(
+    src: &[u8],
+    (sw, sh, stride): (usize, usize, usize),
+    (fx, fy): (usize, usize),
+    (dw, dh): (usize, usize),
+) -> Vec<u8> {
+    let mut dst = vec![0; dw * dh];
+
+    for row in 0..dh {
+        for col in 0..dw {
+            let (mut sum, mut n) = (0u32, 0u32);
+
+            for sy in row * fy..(row * fy + fy).min(sh) {
+                for sx in col * fx..(col * fx + fx).min(sw) {
+                    sum += src[sy * stride + sx] as u32;
+                    n += 1;
+                }
+            }
+
+            dst[row * dw + col] = ((sum + n / 2) / n.max(1)) as u8;
+        }
+    }
+
+    dst
+}
+
+/// Interpolates between two samples, result is scaled by 2^16.
+#[inline]
+fn lerp(a: u8, b: u8, w: u32) -> u64 {
+    a as u64 * (0x10000 - w) as u64 + b as u64 * w as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_rect_widens_to_even_coordinates() {
+        assert_eq!(crop_rect((1, 1, 3, 3), (10, 10)), Some((0, 0, 4, 4)));
+        assert_eq!(crop_rect((2, 4, 4, 2), (10, 10)), Some((2, 4, 4, 2)));
+    }
+
+    #[test]
+    fn crop_rect_clips_to_even_frame() {
+        assert_eq!(crop_rect((4, 4, 100, 100), (11, 9)), Some((4, 4, 6, 4)));
+        assert_eq!(crop_rect((10, 0, 4, 4), (10, 10)), None);
+        assert_eq!(crop_rect((2, 2, 0, 0), (10, 10)), None);
+    }
+
+    #[test]
+    fn rgb_roundtrips_through_decoder_conversion() {
+        use crate::{Bt601FullRange, ColorConvert, YuvPlanes};
+
+        for rgb in [
+            [0, 0, 0],
+            [255, 255, 255],
+            [128, 128, 128],
+            [255, 0, 0],
+            [30, 200, 90],
+        ] {
+            let [r, g, b] = rgb.map(i32::from);
+            let y = [luma(r, g, b)];
+            let (u, v) = chroma(r, g, b);
+            let planes = YuvPlanes {
+                width: 1,
+                height: 1,
+                y: &y,
+                u: &[u],
+                v: &[v],
+                strides: (1, 1, 1),
+            };
+
+            let mut out = [0; 3];
+            Bt601FullRange.i420_to_rgb8(&planes, &mut out);
+
+            assert!(
+                out.iter().zip(rgb).all(|(&a, b)| a.abs_diff(b) <= 2),
+                "{rgb:?} came back as {out:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn downsample_plane_averages_blocks() {
+        // one byte of stride padding per row, which must not be sampled
+        let src = [10, 20, 30, 40, 255, 30, 40, 50, 60, 255];
+
+        assert_eq!(downsample_plane(&src, (4, 2, 5), (2, 2), (2, 1)), [25, 45]);
+        assert_eq!(downsample_plane(&src, (4, 2, 5), (4, 1), (1, 2)), [25, 45]);
+    }
+
+    #[test]
+    fn downsample_plane_averages_partial_edge_blocks() {
+        assert_eq!(
+            downsample_plane(&[10, 20, 31], (3, 1, 3), (2, 1), (2, 1)),
+            [15, 31]
+        );
+        assert_eq!(downsample_plane(&[1, 2], (2, 1, 2), (2, 1), (1, 1)), [2]);
+    }
+}